@@ -6,20 +6,29 @@ use codespan_reporting::files::SimpleFiles;
 use lalrpop_util::lexer::Token;
 use lalrpop_util::ParseError;
 use log::debug;
-use predikit::comp::ast::{AstFile, AstFileChecks, AstFileTools};
+use predikit::comp::ast::{AstFile, AstFileChecks, AstFileTools, AstInclude};
+use predikit::comp::compiler::typecheck_pruned_thresholds;
 use predikit::comp::errors::{show_fancy_compile_errors, show_fancy_error};
-use predikit::comp::tokens::LexicalError;
+use predikit::comp::includes::resolve_include;
+use predikit::comp::tokens::{parse_duration_str, LexicalError};
 use predikit::comp::{pkparser, CompiledCheckFileOut};
+use predikit::data::bus::{ChkEventBus, ChkEventBusHandle};
+use predikit::data::dryrun::dry_run_lines;
 use predikit::data::events::{desc_from_instances, ChkDescMap};
 use predikit::data::instance::{ChkInstance, RunEnv};
-use predikit::data::ChkDefRegistry;
-use predikit::formatters::default::DefaultOutputFormatter;
-use predikit::formatters::FormatterConfig;
+use predikit::data::jobs::ChkJobSlots;
+use predikit::data::params::{ChkActualParam, ChkActualParams};
+use predikit::data::process::TIMEOUT;
+use predikit::data::tags::TagFilter;
+use predikit::data::{ChkDefRegistry, ParsedDuration};
+use predikit::formatters::{FormatterConfig, FormatterRegistry, DEFAULT_FORMAT};
+use predikit::functions::builtin::JOBS;
+use std::collections::HashSet;
 use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
-use std::sync::mpsc::channel;
-use std::thread;
+use std::io::{BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
 pub mod predikit;
 
@@ -38,23 +47,107 @@ pub struct Cli {
     #[arg(long, short, action)]
     parse_only: bool,
 
+    /// Only run checks with at least one of these tags, ex: --tags net,slow
+    #[arg(long, value_delimiter = ',')]
+    tags: Vec<String>,
+
+    /// Don't run checks with any of these tags
+    #[arg(long, value_delimiter = ',')]
+    exclude_tags: Vec<String>,
+
+    /// Default time limit for the commands and hooks run by each check, ex: --timeout 30s.
+    /// A check's timeout param overrides this.
+    #[arg(long, value_parser = parse_timeout)]
+    timeout: Option<ParsedDuration>,
+
+    /// Run the children of every group in parallel, with up to N checks running at a time
+    /// in the whole run. Without this, groups with parallel: true run all of their children at once
+    #[arg(long, short, value_parser = clap::value_parser!(i64).range(1..))]
+    jobs: Option<i64>,
+
+    /// Stop running checks after the first root check that fails or errors.
+    /// The checks that didn't run are reported as skipped
+    #[arg(long, action)]
+    fail_fast: bool,
+
+    /// Show the commands, hooks and paths each check would use, without running anything
+    #[arg(long, action)]
+    dry_run: bool,
+
+    /// How results are shown: default, json, ndjson, junit or tap
+    #[arg(long, default_value = DEFAULT_FORMAT, value_parser = parse_format)]
+    format: String,
+
+    /// Write the --format report to a file. The default output is still shown
+    #[arg(long, short, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Also write a report to a file in any format except default, ex: --report junit:report.xml.
+    /// Can be used more than once
+    #[arg(long = "report", value_name = "FORMAT:FILE", value_parser = parse_report)]
+    reports: Vec<(String, PathBuf)>,
+
     /// Enable debug logging
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
 }
 
-fn spawn_listener(
-    listener_config: FormatterConfig,
+fn parse_timeout(s: &str) -> Result<ParsedDuration, String> {
+    match parse_duration_str(s) {
+        Some(d) if d.duration.is_zero() => Err("timeout must be greater than 0".to_owned()),
+        Some(d) => Ok(d),
+        None => Err(format!("invalid duration [{}], ex: 30s or 5m", s)),
+    }
+}
+
+fn parse_format(s: &str) -> Result<String, String> {
+    let registry = FormatterRegistry::new_with_builtins();
+    if registry.names().contains(&s) {
+        Ok(s.to_owned())
+    } else {
+        Err(format!(
+            "unknown format [{}], expected one of: {}",
+            s,
+            registry.names().join(", ")
+        ))
+    }
+}
+
+fn parse_report(s: &str) -> Result<(String, PathBuf), String> {
+    let Some((format, path)) = s.split_once(':') else {
+        return Err(format!("invalid report [{}], ex: junit:report.xml", s));
+    };
+    if path.is_empty() {
+        return Err(format!("missing a file for the {} report", format));
+    }
+    // the default output is always shown on the console
+    if format == DEFAULT_FORMAT {
+        return Err(format!(
+            "the {} format can't be written to a file, ex: junit:report.xml",
+            DEFAULT_FORMAT
+        ));
+    }
+    Ok((parse_format(format)?, PathBuf::from(path)))
+}
+
+// Each formatter subscribes to the event bus, and gets it's own thread
+fn start_event_bus(
+    listeners: Vec<(String, FormatterConfig)>,
 ) -> (
     std::sync::mpsc::Sender<ChkLifecycleEvent>,
-    thread::JoinHandle<()>,
+    ChkEventBusHandle,
 ) {
-    let (tx, rx) = channel();
-    let iot = thread::spawn(move || {
-        let mut listener = DefaultOutputFormatter::new(listener_config);
-        listener.process_events(rx);
-    });
-    (tx, iot)
+    let registry = FormatterRegistry::new_with_builtins();
+    let mut bus = ChkEventBus::new();
+    for (format, listener_config) in listeners {
+        // the format was checked when the cli was parsed
+        let mut listener = registry.create(&format, listener_config).unwrap();
+        bus.subscribe(move |rx| {
+            listener.process_events(rx);
+            listener.term();
+        });
+    }
+    bus.start()
 }
 
 fn load_source_from_file(filename: &PathBuf) -> Result<String, String> {
@@ -141,13 +234,14 @@ pub fn process_cli() -> bool {
         return false;
     }
 
-    let listener_config = FormatterConfig {
-        color: !cli.no_color,
-    };
+    if cli.output.is_some() && cli.format == DEFAULT_FORMAT {
+        println!("--output needs a --format, ex: --format junit --output report.xml");
+        return false;
+    }
 
     // compilation starts here
-    let ast_files = parse_files(&cli);
-    if let None = ast_files {
+    let ast_files = parse_files(&cli.infiles);
+    if ast_files.is_none() {
         //println!("Fatal compilation error");
         return false;
     }
@@ -161,7 +255,7 @@ pub fn process_cli() -> bool {
         return false;
     }
 
-    let cfas = compile_checks(ast_file_checks, &fns);
+    let mut cfas = compile_checks(ast_file_checks, &fns);
     if process_errors(&cfas) {
         return false;
     }
@@ -175,22 +269,106 @@ pub fn process_cli() -> bool {
         return true;
     }
 
-    let (tx, iot) = spawn_listener(listener_config);
+    let tag_filter = TagFilter::new(cli.tags.clone(), cli.exclude_tags.clone());
+    for cfa in &mut cfas {
+        cfa.instances = tag_filter.prune(std::mem::take(&mut cfa.instances));
+        // pruning can leave a threshold group with fewer children than it needs
+        typecheck_pruned_thresholds(cfa);
+    }
+    if process_errors(&cfas) {
+        return false;
+    }
+
+    if cli.dry_run {
+        for cfa in cfas {
+            println!(
+                "\n* Dry run of {}:",
+                cfa.filename.unwrap_or("<no file>".to_string())
+            );
+            dry_run_lines(&cfa.instances)
+                .iter()
+                .for_each(|line| println!("{}", line));
+        }
+        return true;
+    }
+
+    let color = !cli.no_color;
+    // --output is the same as a report in the --format, with the default output on the console
+    let mut reports = cli.reports.clone();
+    let console_format = match &cli.output {
+        Some(path) => {
+            reports.insert(0, (cli.format.clone(), path.clone()));
+            DEFAULT_FORMAT.to_owned()
+        }
+        None => cli.format.clone(),
+    };
+    let mut listeners = vec![(
+        console_format,
+        FormatterConfig {
+            color,
+            output: None,
+        },
+    )];
+    for (format, path) in reports {
+        let file = match File::create(&path) {
+            Ok(f) => f,
+            Err(e) => {
+                println!("Can't create {}: {}", path.display(), e);
+                return false;
+            }
+        };
+        listeners.push((
+            format,
+            FormatterConfig {
+                color,
+                output: Some(Box::new(BufWriter::new(file))),
+            },
+        ));
+    }
+    let (tx, bus) = start_event_bus(listeners);
 
     // TODO: maybe we don't need a RunEnv
-    // TODO: thread through global values? Maybe not, I have to think through this a bit more
+    let mut global_config = ChkActualParams::new();
+    if let Some(timeout) = &cli.timeout {
+        global_config.insert(
+            TIMEOUT.to_owned(),
+            ChkActualParam::new_duration(TIMEOUT.to_owned(), timeout.clone(), 0..0),
+        );
+    }
+    if let Some(jobs) = cli.jobs {
+        global_config.insert(
+            JOBS.to_owned(),
+            ChkActualParam::new_int(JOBS.to_owned(), jobs, 0..0),
+        );
+    }
     let run_env = RunEnv {
         emitter: Some(tx),
-        ..RunEnv::default()
+        global_config,
+        job_slots: cli
+            .jobs
+            .map(|jobs| Arc::new(ChkJobSlots::new(jobs as usize))),
     };
 
     let mut res = true;
+    // with --fail-fast, the checks in the rest of the files are skipped too
+    let mut stopped = false;
+    let started = Instant::now();
+    run_env.emit(ChkLifecycleEvent::AllStart);
     for cfa in cfas {
-        if !run_checks(cfa.instances, cfa.filename, &run_env) {
+        if !run_checks(
+            cfa.instances,
+            cfa.filename,
+            &run_env,
+            cli.fail_fast,
+            &mut stopped,
+        ) {
             res = false;
         }
     }
-    iot.join().unwrap();
+    run_env.emit(ChkLifecycleEvent::AllFinish(started.elapsed()));
+    // the event bus shuts down once every sender is gone
+    drop(run_env);
+    bus.join();
     res
 }
 
@@ -205,7 +383,7 @@ fn huge_error_fn(filename: String, source: &String, e: ParseError<usize, Token<'
                 &(start..end),
                 "Fatal compilation error",
                 &msg,
-                &source,
+                source,
                 &Some(filename),
             );
         }
@@ -216,7 +394,7 @@ fn huge_error_fn(filename: String, source: &String, e: ParseError<usize, Token<'
                 &loc,
                 "Fatal compilation error",
                 msg,
-                &source,
+                source,
                 &Some(filename),
             );
         }
@@ -233,12 +411,12 @@ fn huge_error_fn(filename: String, source: &String, e: ParseError<usize, Token<'
                 &loc,
                 "Fatal compilation error",
                 msg,
-                &source,
+                source,
                 &Some(filename),
             );
         }
         lalrpop_util::ParseError::User { error } => match error {
-            predikit::comp::tokens::LexicalError::InvalidInteger(parse_int_error) => {
+            predikit::comp::tokens::LexicalError::InvalidInteger(_parse_int_error) => {
                 todo!()
             }
             predikit::comp::tokens::LexicalError::InvalidType(range) => {
@@ -246,7 +424,7 @@ fn huge_error_fn(filename: String, source: &String, e: ParseError<usize, Token<'
                     &(range),
                     "Invalid type",
                     "This is not a valid type",
-                    &source,
+                    source,
                     &Some(filename),
                 );
             }
@@ -255,7 +433,7 @@ fn huge_error_fn(filename: String, source: &String, e: ParseError<usize, Token<'
                     &(range),
                     "Invalid duration",
                     "This is not a valid duration literal",
-                    &source,
+                    source,
                     &Some(filename),
                 );
             }
@@ -264,7 +442,7 @@ fn huge_error_fn(filename: String, source: &String, e: ParseError<usize, Token<'
                     &(range),
                     "Invalid path",
                     "This is not a valid path literal",
-                    &source,
+                    source,
                     &Some(filename),
                 );
             }
@@ -273,7 +451,7 @@ fn huge_error_fn(filename: String, source: &String, e: ParseError<usize, Token<'
                     &(range),
                     &format!("Invalid {}: {}", conv_type, msg),
                     "This is not a valid path literal",
-                    &source,
+                    source,
                     &Some(filename),
                 );
             }
@@ -282,7 +460,7 @@ fn huge_error_fn(filename: String, source: &String, e: ParseError<usize, Token<'
                     &(range),
                     "Invalid token",
                     "This token is not valid",
-                    &source,
+                    source,
                     &Some(filename),
                 );
             }
@@ -292,52 +470,130 @@ fn huge_error_fn(filename: String, source: &String, e: ParseError<usize, Token<'
 
 // Don't bother returning a Result, the errors in this fn are fatal and predikit
 // will exit after displaying these errors.
-fn parse_files(cli: &Cli) -> Option<(Vec<AstFileChecks>, Vec<AstFileTools>)> {
-    let mut ast_file_checks: Vec<AstFileChecks> = vec![];
-    let mut ast_file_tools: Vec<AstFileTools> = vec![];
+fn parse_files(infiles: &[PathBuf]) -> Option<(Vec<AstFileChecks>, Vec<AstFileTools>)> {
+    let mut ast_files: Vec<AstFile> = vec![];
+    // canonical paths of every file parsed so far, a file that is included
+    // more than once (or also passed on the command line) is only parsed once
+    let mut seen: HashSet<PathBuf> = HashSet::new();
     // file DB for showing error messages. This is just a crude impl, and needs to be thought out.
     let _source_files: SimpleFiles<String, String> = SimpleFiles::new();
 
     // Compile each file to an ast, skipping those that fail to parse
-    for infile in &cli.infiles {
-        debug!("Input file = {}", infile.display());
-
-        let source = match load_source_from_file(infile) {
-            Ok(source) => source,
-            Err(e) => {
-                let msg = format!("error reading {}:\n{}", infile.to_string_lossy(), e);
-                println!("{}", msg);
-                return None;
-            }
-        };
-        let result = pkparser::TopLevelParser::new().parse(&source);
-        if let Err(e) = result {
-            // it's in the name
-            huge_error_fn(infile.to_string_lossy().to_string(), &source, e);
+    for infile in infiles {
+        let mut include_stack: Vec<PathBuf> = vec![];
+        if !parse_file(infile, &mut include_stack, &mut seen, &mut ast_files) {
             return None;
         }
+    }
 
-        let (checks, tools) = result.unwrap();
+    let mut ast_file_checks: Vec<AstFileChecks> = vec![];
+    let mut ast_file_tools: Vec<AstFileTools> = vec![];
+    let canonical = |f: &PathBuf| f.canonicalize().unwrap_or_else(|_| f.clone());
+    let cli_files: HashSet<PathBuf> = infiles.iter().map(canonical).collect();
+    for ast_file in ast_files {
+        // included tool libraries usually don't contain any checks, don't report on them.
+        // Files on the command line are always reported, even when they're empty.
+        let from_cli = ast_file
+            .filename
+            .as_ref()
+            .is_some_and(|f| cli_files.contains(&canonical(&PathBuf::from(f))));
+        if from_cli || !ast_file.checks.check_defs.is_empty() {
+            ast_file_checks.push(ast_file.checks);
+        }
+        ast_file_tools.push(ast_file.tools);
+    }
+    Some((ast_file_checks, ast_file_tools))
+}
 
-        // checks and tools need to carry around a filename so they can produce an
-        // appropriate error message later on
-        let checks = AstFileChecks::new(Some(infile.display().to_string()), checks);
-        let tools = AstFileTools::new(Some(infile.display().to_string()), tools);
+// Parse a single file, followed by any files it includes. Included files are
+// added to ast_files as their own file so tools and checks keep the filename
+// they were defined in. include_stack is the chain of files currently being
+// included, and is used to detect cycles.
+fn parse_file(
+    infile: &PathBuf,
+    include_stack: &mut Vec<PathBuf>,
+    seen: &mut HashSet<PathBuf>,
+    ast_files: &mut Vec<AstFile>,
+) -> bool {
+    debug!("Input file = {}", infile.display());
+
+    let source = match load_source_from_file(infile) {
+        Ok(source) => source,
+        Err(e) => {
+            let msg = format!("error reading {}:\n{}", infile.to_string_lossy(), e);
+            println!("{}", msg);
+            return false;
+        }
+    };
 
-        // leaving this around, as it's helpful to write out this struct to a file during tracing
-        let ast_file = AstFile {
-            filename: Some(infile.display().to_string()),
-            checks,
-            tools,
-        };
+    let canonical = infile.canonicalize().unwrap_or_else(|_| infile.clone());
+    if !seen.insert(canonical.clone()) {
+        debug!("Skipping {}, it has already been parsed", infile.display());
+        return true;
+    }
+
+    let result = pkparser::TopLevelParser::new().parse(&source);
+    if let Err(e) = result {
+        // it's in the name
+        huge_error_fn(infile.to_string_lossy().to_string(), &source, e);
+        return false;
+    }
+
+    let mut ast_file = result.unwrap();
+    let filename = Some(infile.display().to_string());
+    ast_file.set_filename(filename.clone());
 
-        let checks = ast_file.checks;
-        let tools = ast_file.tools;
+    let includes = std::mem::take(&mut ast_file.includes);
+    ast_files.push(ast_file);
 
-        ast_file_checks.push(checks);
-        ast_file_tools.push(tools);
+    // pop the file even when an include fails, so the stack stays balanced
+    include_stack.push(canonical);
+    let parsed = parse_includes(infile, includes, &source, include_stack, seen, ast_files);
+    include_stack.pop();
+    parsed
+}
+
+fn parse_includes(
+    infile: &Path,
+    includes: Vec<AstInclude>,
+    source: &String,
+    include_stack: &mut Vec<PathBuf>,
+    seen: &mut HashSet<PathBuf>,
+    ast_files: &mut Vec<AstFile>,
+) -> bool {
+    let filename = Some(infile.display().to_string());
+    for include in includes {
+        let paths = match resolve_include(infile, &include.path) {
+            Ok(paths) => paths,
+            Err(e) => {
+                show_fancy_error(
+                    &include.content_address,
+                    "Invalid include",
+                    &e,
+                    source,
+                    &filename,
+                );
+                return false;
+            }
+        };
+        for path in paths {
+            let canonical_include = path.canonicalize().unwrap_or_else(|_| path.clone());
+            if include_stack.contains(&canonical_include) {
+                show_fancy_error(
+                    &include.content_address,
+                    "Include cycle detected",
+                    &format!("{} is already being included", path.display()),
+                    source,
+                    &filename,
+                );
+                return false;
+            }
+            if !parse_file(&path, include_stack, seen, ast_files) {
+                return false;
+            }
+        }
     }
-    Some((ast_file_checks, ast_file_tools))
+    true
 }
 
 fn compile_checks(
@@ -348,20 +604,121 @@ fn compile_checks(
     crate::predikit::comp::compiler::compile_checks_to_asts(fns, all_ast_file_checks)
 }
 
-fn run_checks(root_checks: Vec<ChkInstance>, filename: Option<String>, run_env: &RunEnv) -> bool {
+fn run_checks(
+    root_checks: Vec<ChkInstance>,
+    filename: Option<String>,
+    run_env: &RunEnv,
+    fail_fast: bool,
+    stopped: &mut bool,
+) -> bool {
     // TODO: this is pretty expensive and should be rethought
     let descs: ChkDescMap = desc_from_instances(&root_checks);
     run_env.emit(ChkLifecycleEvent::Init(descs, filename.clone()));
 
     let mut final_result = true;
     for check in root_checks {
+        if *stopped {
+            check.report_skipped(run_env);
+            continue;
+        }
         let res = check.run_check_maybe_retry(run_env);
-        if !res.is_check_pass() {
-            // could be a fail OR an error, so just use !is_check_pass
+        if !res.is_check_pass() && !res.is_check_skipped() {
+            // could be a fail OR an error, so just use !is_check_pass.
+            // A skipped check didn't run, so it doesn't fail the run
             final_result = false;
+            *stopped = fail_fast;
         }
     }
 
     run_env.emit(ChkLifecycleEvent::Term(filename.clone()));
     final_result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn parse_to_ast_files(infile: &PathBuf) -> Option<Vec<AstFile>> {
+        let mut include_stack = vec![];
+        let mut seen = HashSet::new();
+        let mut ast_files = vec![];
+        let parsed = parse_file(infile, &mut include_stack, &mut seen, &mut ast_files);
+        assert!(include_stack.is_empty());
+        parsed.then_some(ast_files)
+    }
+
+    #[test]
+    fn test_parse_report() {
+        assert_eq!(
+            Ok(("junit".to_owned(), PathBuf::from("report.xml"))),
+            parse_report("junit:report.xml")
+        );
+        assert!(parse_report("junit:").is_err());
+        assert!(parse_report("nope:report.txt").is_err());
+        // the default formatter only writes to the console
+        assert!(parse_report("default:out.txt").is_err());
+    }
+
+    #[test]
+    fn test_include_cycles() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+
+        let direct = tmp_dir.path().join("direct.pk");
+        fs::write(&direct, "include \"direct.pk\"\n").unwrap();
+        assert!(parse_to_ast_files(&direct).is_none());
+
+        let a = tmp_dir.path().join("a.pk");
+        let b = tmp_dir.path().join("b.pk");
+        let c = tmp_dir.path().join("c.pk");
+        fs::write(&a, "include \"b.pk\"\n").unwrap();
+        fs::write(&b, "include \"c.pk\"\n").unwrap();
+        fs::write(&c, "include \"a.pk\"\n").unwrap();
+        assert!(parse_to_ast_files(&a).is_none());
+    }
+
+    #[test]
+    fn test_parse_files_without_checks() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let tools = tmp_dir.path().join("tools.pk");
+        fs::write(&tools, "tool t? {\n cmd_template: \"true\"\n}\n").unwrap();
+        let checks = tmp_dir.path().join("checks.pk");
+        fs::write(&checks, "test exists? { path: \"/\" }\n").unwrap();
+        let main = tmp_dir.path().join("main.pk");
+        fs::write(&main, "include \"tools.pk\"\ninclude \"checks.pk\"\n").unwrap();
+
+        // main.pk has no checks of it's own but is on the command line, the included
+        // tools.pk has no checks and isn't reported
+        let (ast_file_checks, ast_file_tools) = parse_files(std::slice::from_ref(&main)).unwrap();
+        assert_eq!(3, ast_file_tools.len());
+        let filenames: Vec<String> = ast_file_checks
+            .into_iter()
+            .filter_map(|checks| checks.filename)
+            .collect();
+        assert_eq!(
+            vec![main.display().to_string(), checks.display().to_string()],
+            filenames
+        );
+    }
+
+    #[test]
+    fn test_include_twice() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let lib_dir = tmp_dir.path().join("lib");
+        fs::create_dir(&lib_dir).unwrap();
+        fs::write(lib_dir.join("tools.pk"), "test exists? { path: \"/\" }\n").unwrap();
+
+        // included directly, through a glob and through another include
+        let other = tmp_dir.path().join("other.pk");
+        fs::write(&other, "include \"lib/tools.pk\"\n").unwrap();
+        let main = tmp_dir.path().join("main.pk");
+        fs::write(
+            &main,
+            "include \"lib/tools.pk\"\ninclude \"lib/*.pk\"\ninclude \"other.pk\"\n",
+        )
+        .unwrap();
+
+        let ast_files = parse_to_ast_files(&main).unwrap();
+        assert_eq!(3, ast_files.len());
+    }
+}
@@ -5,8 +5,10 @@ use super::data::instance::{ChkInstance, ContentAddress};
 pub mod ast;
 pub mod compiler;
 pub mod errors;
+pub mod includes;
 pub mod tokens;
 pub mod validators;
+pub mod vars;
 
 use lalrpop_util::lalrpop_mod;
 lalrpop_mod!(pub pkparser, "/predikit/comp/pkparser.rs");
@@ -72,7 +74,14 @@ impl<'a> CompiledCheckFileOut<'a> {
 #[cfg(test)]
 mod tests {
 
-    use crate::predikit::{comp::pkparser, data::ChkParamType};
+    use crate::predikit::{
+        comp::pkparser,
+        data::{
+            matchers::ChkMatcherOp,
+            params::{ChkParamInternalValue, NamedType},
+            ChkParamType,
+        },
+    };
 
     #[test]
     fn test_param_int() {
@@ -104,7 +113,63 @@ mod tests {
             .parse("somebool: true")
             .unwrap();
         assert!(param_bool.is_type(&ChkParamType::PkBool));
-        assert_eq!(true, param_bool.get_bool());
+        assert!(param_bool.get_bool());
+    }
+
+    #[test]
+    fn test_param_list() {
+        let ports = pkparser::ActualParamParser::new()
+            .parse("ports: [22, 80, 443,]")
+            .unwrap();
+        assert!(ports.is_type(&ChkParamType::PkList(Box::new(ChkParamType::PkInt))));
+        assert_eq!(3, ports.get_list().len());
+
+        let paths = pkparser::ActualParamParser::new()
+            .parse("paths: [p($HOME/.zshrc), \"/tmp\"]")
+            .unwrap();
+        assert!(paths.is_type(&ChkParamType::PkList(Box::new(ChkParamType::PkPath))));
+
+        let empty = pkparser::ActualParamParser::new()
+            .parse("empty: []")
+            .unwrap();
+        assert!(empty.get_list().is_empty());
+    }
+
+    #[test]
+    fn test_param_map() {
+        let labels = pkparser::ActualParamParser::new()
+            .parse("labels: { env: \"prod\" team: \"infra\", ports: [1, 2] }")
+            .unwrap();
+        let map = labels.get_map();
+        assert_eq!(
+            Some(&ChkParamInternalValue::PkString("prod".to_owned())),
+            map.get("env")
+        );
+        assert_eq!("List(Int)", map.get("ports").unwrap().type_name());
+    }
+
+    #[test]
+    fn test_param_collection_types() {
+        let p = pkparser::ActualParamParser::new()
+            .parse("type: List(Int)")
+            .unwrap();
+        assert_eq!(
+            &NamedType::PtnList(Box::new(NamedType::PtnInt)),
+            p.get_named_type()
+        );
+        let p = pkparser::ActualParamParser::new()
+            .parse("type: Map( String )")
+            .unwrap();
+        assert_eq!(
+            &NamedType::PtnMap(Box::new(NamedType::PtnString)),
+            p.get_named_type()
+        );
+        assert!(pkparser::ActualParamParser::new()
+            .parse("type: List(Foo)")
+            .is_err());
+        assert!(pkparser::ActualParamParser::new()
+            .parse("type: Int(String)")
+            .is_err());
     }
 
     #[test]
@@ -136,6 +201,55 @@ mod tests {
         assert_eq!(1, cd.actual_params.keys().len());
     }
 
+    #[test]
+    fn test_include() {
+        let ast_file = pkparser::TopLevelParser::new()
+            .parse(
+                "include \"lib/*.pk\"
+                 test exists? { path: \"/tmp\" }
+                 include \"other.pk\"",
+            )
+            .unwrap();
+        let paths: Vec<_> = ast_file.includes.iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(vec!["lib/*.pk", "other.pk"], paths);
+        assert_eq!(1, ast_file.checks.check_defs.len());
+    }
+
+    #[test]
+    fn test_check_def_matcher() {
+        let cd = pkparser::CheckDefParser::new()
+            .parse("test pkg_version? { pkg: \"zsh\" } = \"5.9\"")
+            .unwrap();
+        let matcher = cd.matcher.unwrap();
+        assert_eq!(ChkMatcherOp::Eq, matcher.op);
+        assert_eq!(
+            ChkParamInternalValue::PkString("5.9".to_string()),
+            matcher.value
+        );
+
+        let cd = pkparser::CheckDefParser::new()
+            .parse("test days_left? { domain: \"example.com\" } >= 30")
+            .unwrap();
+        let matcher = cd.matcher.unwrap();
+        assert_eq!(ChkMatcherOp::Gte, matcher.op);
+        assert_eq!(ChkParamInternalValue::PkInt(30), matcher.value);
+
+        let cd = pkparser::CheckDefParser::new()
+            .parse(r##"test pkg_version? { pkg: "zsh" } =~ r#^5\.\d+#"##)
+            .unwrap();
+        let matcher = cd.matcher.unwrap();
+        assert_eq!(ChkMatcherOp::RegexMatch, matcher.op);
+        assert_eq!(
+            ChkParamInternalValue::PkString(r"^5\.\d+".to_string()),
+            matcher.value
+        );
+
+        let cd = pkparser::CheckDefParser::new()
+            .parse("test exists? { path: \"/tmp\" }")
+            .unwrap();
+        assert!(cd.matcher.is_none());
+    }
+
     #[test]
     fn test_check_def_error() {
         // double { should fail
@@ -147,13 +261,58 @@ mod tests {
         } = urt
         {
             assert_eq!(token.0, 21);
-            assert_eq!(token.1, lalrpop_util::lexer::Token(23, "{"));
+            assert_eq!(token.1, lalrpop_util::lexer::Token(41, "{"));
             assert_eq!(token.2, 22);
         } else {
             panic!("Should have failed");
         }
     }
 
+    #[test]
+    fn test_group_negated_and_retrying() {
+        let s = r#"
+            @not all {
+                retries: 3
+                retry_delay: d(1s)
+                test exists? { path: "/tmp" }
+            }
+            "#;
+        let cd = pkparser::GroupOrCheckParser::new().parse(s).unwrap();
+        assert_eq!("all".to_string(), cd.fn_name);
+        assert!(cd.is_group);
+        assert!(cd.is_negated);
+        assert!(cd.is_retrying);
+
+        let cd = pkparser::GroupOrCheckParser::new()
+            .parse("not any { test exists? { path: \"/tmp\" } }")
+            .unwrap();
+        assert!(cd.is_negated);
+        assert!(!cd.is_retrying);
+    }
+
+    #[test]
+    fn test_wait_def() {
+        let s = r#"
+            wait until_pass wait_for: d(2m) interval: d(1s) {
+                title: "db is up"
+                test port_open? { port: 5432 }
+            }
+            "#;
+        let cd = pkparser::GroupOrCheckParser::new().parse(s).unwrap();
+        assert_eq!("wait_until_pass".to_string(), cd.fn_name);
+        assert!(cd.is_group);
+        assert_eq!(3, cd.actual_params.len());
+        assert_eq!(
+            "2m",
+            cd.actual_params
+                .get("wait_for")
+                .unwrap()
+                .get_duration()
+                .duration_str
+        );
+        assert_eq!(1, cd.children.len());
+    }
+
     #[test]
     fn test_check_nested() {
         let s = r#"
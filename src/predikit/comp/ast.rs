@@ -2,7 +2,9 @@
 
 use std::collections::HashMap;
 
-use crate::predikit::data::{instance::ContentAddress, params::ChkActualParam};
+use crate::predikit::data::{
+    instance::ContentAddress, matchers::ChkMatcher, params::ChkActualParam,
+};
 
 // These structs represent "raw" (pre-typechecked) Checks, Tools, and Parameters.
 // Check (function) names may not be defined and types may be incorrect (among other things
@@ -15,18 +17,55 @@ pub struct AstFile {
     pub filename: Option<String>,
     pub tools: AstFileTools,
     pub checks: AstFileChecks,
+    pub includes: Vec<AstInclude>,
+}
+
+impl AstFile {
+    pub fn new(
+        filename: Option<String>,
+        check_defs: Vec<AstCheckDef>,
+        tool_defs: Vec<AstToolDef>,
+        includes: Vec<AstInclude>,
+        vars: AstVars,
+    ) -> Self {
+        Self {
+            filename: filename.clone(),
+            tools: AstFileTools::new(filename.clone(), tool_defs),
+            checks: AstFileChecks::new(filename, check_defs, vars),
+            includes,
+        }
+    }
+
+    // checks and tools need to carry around a filename so they can produce an
+    // appropriate error message later on
+    pub fn set_filename(&mut self, filename: Option<String>) {
+        self.filename = filename.clone();
+        self.tools.filename = filename.clone();
+        self.checks.filename = filename;
+    }
+}
+
+// ex: include "lib/*.pk"
+// The path is relative to the file that contains the include, and may be a glob.
+#[derive(Debug, Clone)]
+pub struct AstInclude {
+    pub path: String,
+    pub content_address: ContentAddress,
 }
 
 #[derive(Debug, Clone)]
 pub struct AstFileChecks {
     pub filename: Option<String>,
     pub check_defs: Vec<AstCheckDef>,
+    // file level vars, visible to every check in the file
+    pub vars: AstVars,
 }
 impl AstFileChecks {
-    pub fn new(filename: Option<String>, check_defs: Vec<AstCheckDef>) -> Self {
+    pub fn new(filename: Option<String>, check_defs: Vec<AstCheckDef>, vars: AstVars) -> Self {
         Self {
             filename,
             check_defs,
+            vars,
         }
     }
 }
@@ -51,6 +90,9 @@ pub enum TopLevelItem {
     Check(AstCheckDef),
     Group(AstCheckDef),
     Tool(AstToolDef),
+    Query(AstToolDef),
+    Include(AstInclude),
+    Vars(AstVars),
 }
 
 #[derive(Debug, Clone)]
@@ -62,10 +104,37 @@ pub struct AstCheckDef {
     pub children: Vec<AstCheckDef>,
     pub content_address: ContentAddress,
     pub is_group: bool,
+    // only queries have a matcher, ex: `test pkg_version? { pkg: "zsh" } = "5.9"`
+    pub matcher: Option<ChkMatcher>,
+    // only groups can define vars
+    pub vars: AstVars,
+    // set for a `for pkg in [...] { ... }` loop, the children are the body of the loop
+    pub for_loop: Option<AstForLoop>,
+    // set for `when: test ... { }`, the check only runs if the guard passes.
+    // A `when: "shell command"` guard is a regular param.
+    pub guard: Option<Box<AstCheckDef>>,
+}
+
+// an item in the body of a check or group, before params and the guard are split apart
+pub enum AstCheckParam {
+    Param(ChkActualParam),
+    Guard(Box<AstCheckDef>),
+}
+
+// ex: for pkg in ["docker", "git"] { ... }
+// values is either a list literal or a reference to a var that holds a list
+#[derive(Debug, Clone)]
+pub struct AstForLoop {
+    pub var_name: String,
+    pub values: ChkActualParam,
 }
 
 pub type AstActualParams = HashMap<String, ChkActualParam>;
 
+// ex: vars { base: p($HOME/app) port: 8080 } or let port: 8080
+// A Vec instead of a map, as a var can reference vars defined before it
+pub type AstVars = Vec<ChkActualParam>;
+
 pub type AstToolInstanceParams = HashMap<String, AstActualParams>;
 
 #[derive(Debug, Clone)]
@@ -91,6 +160,8 @@ pub struct AstToolDef {
     pub name: String,
     pub content_address: ContentAddress,
     pub params: AstToolDefParams,
+    // defined with `query` instead of `tool`
+    pub is_query: bool,
 }
 impl AstToolDef {
     pub fn new(name: String, content_address: ContentAddress, params: AstToolDefParams) -> Self {
@@ -98,6 +169,20 @@ impl AstToolDef {
             name,
             content_address,
             params,
+            is_query: false,
+        }
+    }
+
+    pub fn new_query(
+        name: String,
+        content_address: ContentAddress,
+        params: AstToolDefParams,
+    ) -> Self {
+        Self {
+            name,
+            content_address,
+            params,
+            is_query: true,
         }
     }
 }
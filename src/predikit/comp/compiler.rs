@@ -6,29 +6,106 @@ use log::debug;
 
 use crate::predikit::{
     comp::CompilerErrorType,
-    data::{instance::ChkInstance, tools::ToolDef, ChkDefRegistry, ChkFormalParam, ChkParamType},
-    functions::builtin_tools::metadef_tool,
+    data::{
+        instance::{ChkInstance, HOOKS},
+        params::{ChkActualParam, ChkParamInternalValue},
+        retry::{RETRIES, RETRY_BACKOFF, RETRY_FOR},
+        tools::ToolDef,
+        ChkDefRegistry, ChkFormalParam, ChkParamType,
+    },
+    functions::{
+        builtin::THRESHOLD,
+        builtin_tools::metadef_tool,
+        waiting::{WAIT_FOR, WAIT_INTERVAL},
+    },
 };
 
 use super::{
     ast::{AstCheckDef, AstFileChecks, AstFileTools},
+    vars::VarScope,
     CompileError, CompiledCheckFileOut,
 };
 
 // Note that this does NOT return a fully typechecked ChkInstance (hence private visibility).:
 // Typechecking happens later. TODO: create a pre-typechecked ChkInstance type? Might not be worth it.
+// A for loop expands into a copy of it's body for each value, everything else is
+// a single instance. Each expanded instance gets it's own instance_id.
+fn make_check_instances<'a>(
+    cfo: &mut CompiledCheckFileOut,
+    fns: &'a ChkDefRegistry,
+    ast_check_def: AstCheckDef,
+    scope: &VarScope,
+) -> Option<Vec<ChkInstance<'a>>> {
+    let Some(for_loop) = ast_check_def.for_loop else {
+        return make_check_instance(cfo, fns, ast_check_def, scope).map(|inst| vec![inst]);
+    };
+
+    let values = match scope.substitute(&for_loop.values) {
+        Ok(values) => values,
+        Err(msg) => {
+            cfo.add_error(cfo.filename.clone(), for_loop.values.content_address, msg);
+            return None;
+        }
+    };
+    let ChkParamInternalValue::PkList(items) = &values.value else {
+        cfo.add_error(
+            cfo.filename.clone(),
+            values.content_address.clone(),
+            format!(
+                "A for loop needs a List to iterate over, got type {}",
+                values.type_name()
+            ),
+        );
+        return None;
+    };
+
+    let mut insts = Vec::new();
+    for item in items {
+        let loop_var = ChkActualParam {
+            name: for_loop.var_name.clone(),
+            value: item.clone(),
+            content_address: values.content_address.clone(),
+        };
+        let iteration_scope = scope.with_loop_var(loop_var);
+        for child in &ast_check_def.children {
+            insts.extend(make_check_instances(
+                cfo,
+                fns,
+                child.clone(),
+                &iteration_scope,
+            )?);
+        }
+    }
+    Some(insts)
+}
+
+// `{{var}}` references in params are resolved here, using the vars in scope
+// for the check. A group's vars are visible to the group and all of it's children.
 fn make_check_instance<'a>(
     cfo: &mut CompiledCheckFileOut,
     fns: &'a ChkDefRegistry,
     ast_check_def: AstCheckDef,
+    scope: &VarScope,
 ) -> Option<ChkInstance<'a>> {
+    let scope = scope.with_vars(cfo, &ast_check_def.vars);
+
     let mut children = Vec::new();
     for child in ast_check_def.children {
-        let child_inst = make_check_instance(cfo, fns, child)?;
-        children.push(child_inst);
+        let child_insts = make_check_instances(cfo, fns, child, &scope)?;
+        children.extend(child_insts);
     }
 
-    let actual_params = ast_check_def.actual_params;
+    let mut actual_params = ast_check_def.actual_params;
+    for param in actual_params.values_mut() {
+        if HOOKS.contains(&param.name.as_str()) {
+            *param = scope.substitute_defined(param);
+            continue;
+        }
+        match scope.substitute(param) {
+            Ok(p) => *param = p,
+            Err(msg) => cfo.add_error(cfo.filename.clone(), param.content_address.clone(), msg),
+        }
+    }
 
     let fn_def = if ast_check_def.is_group {
         fns.group_fns.get(&ast_check_def.fn_name)
@@ -46,6 +123,24 @@ fn make_check_instance<'a>(
     }
     let fn_def = fn_def.unwrap();
 
+    // a guard check sees the same vars as the check it guards
+    let guard = match ast_check_def.guard {
+        Some(guard) => {
+            if let Some(when_cmd) = actual_params.get("when") {
+                cfo.add_error(
+                    cfo.filename.clone(),
+                    when_cmd.content_address.clone(),
+                    format!(
+                        "Check {} can't have both a when: command and a when: check",
+                        fn_def.name
+                    ),
+                );
+            }
+            Some(Box::new(make_check_instance(cfo, fns, *guard, &scope)?))
+        }
+        None => None,
+    };
+
     let mut inst = ChkInstance {
         title: None,
         fn_def,
@@ -56,7 +151,10 @@ fn make_check_instance<'a>(
         is_retrying: ast_check_def.is_retrying,
         instance_id: cfo.next_id(),
         content_address: ast_check_def.content_address,
-        is_query: false,
+        is_query: fn_def.is_query,
+        matcher: ast_check_def.matcher,
+        loop_vars: scope.loop_vars().clone(),
+        guard,
     };
     inst.materialize_formal_params();
     Some(inst)
@@ -151,11 +249,164 @@ fn typecheck_check_params(cfo: &mut CompiledCheckFileOut, inst: &ChkInstance) {
             );
         }
     }
+
+    // queries need a matcher to produce a result, everything else
+    // passes or fails on it's own
+    if let Some(matcher) = &inst.matcher {
+        if !inst.is_query {
+            cfo.add_error(
+                cfo.filename.clone(),
+                matcher.content_address.clone(),
+                format!(
+                    "{} is not a query and can't be used with a matcher",
+                    inst.fn_def.name
+                ),
+            );
+        } else if let Err(msg) = matcher.validate() {
+            cfo.add_error(
+                cfo.filename.clone(),
+                matcher.content_address.clone(),
+                format!("Invalid matcher for check {}: {}", inst.fn_def.name, msg),
+            );
+        }
+    } else if inst.is_query {
+        cfo.add_error(
+            cfo.filename.clone(),
+            inst.content_address.clone(),
+            format!(
+                "Query {} must be used with a matcher, ex: test {} {{ ... }} = \"some value\"",
+                inst.fn_def.name, inst.fn_def.name
+            ),
+        );
+    }
+
+    if inst.is_retrying {
+        typecheck_retry_params(cfo, inst);
+    }
+
+    if inst.fn_def.formal_params.contains_key(WAIT_FOR) {
+        typecheck_wait_params(cfo, inst);
+    }
+
+    if let Some(n) = invalid_threshold(inst) {
+        cfo.add_error(
+            cfo.filename.clone(),
+            n.content_address.clone(),
+            format!(
+                "Invalid n for group {}({}), n must be between 0 and the number of children ({})",
+                inst.fn_def.name,
+                n.get_int(),
+                inst.children.len()
+            ),
+        );
+    }
+
+    if let Some(guard) = &inst.guard {
+        typecheck_check_params(cfo, guard);
+    }
+
     for child in &inst.children {
         typecheck_check_params(cfo, child);
     }
 }
 
+// threshold groups can't need more passing children than they have,
+// ex: at_least(3) with 2 children can never pass
+fn invalid_threshold<'a>(inst: &'a ChkInstance) -> Option<&'a ChkActualParam> {
+    if !inst.fn_def.is_group {
+        return None;
+    }
+    inst.actual_params.get(THRESHOLD).filter(|n| {
+        let n_value = n.get_int();
+        n_value < 0 || n_value as usize > inst.children.len()
+    })
+}
+
+/// Check threshold groups again once --tags or --exclude-tags has removed checks
+/// from a compiled file, ex: at_least(2) with only one selected child can never pass
+pub fn typecheck_pruned_thresholds(cfo: &mut CompiledCheckFileOut) {
+    fn typecheck(cfo: &mut CompiledCheckFileOut, inst: &ChkInstance) {
+        if let Some(n) = invalid_threshold(inst) {
+            cfo.add_error(
+                cfo.filename.clone(),
+                n.content_address.clone(),
+                format!(
+                    "Invalid n for group {}({}), only {} of it's children are selected by --tags and --exclude-tags",
+                    inst.fn_def.name,
+                    n.get_int(),
+                    inst.children.len()
+                ),
+            );
+        }
+        for child in &inst.children {
+            typecheck(cfo, child);
+        }
+    }
+
+    let instances = std::mem::take(&mut cfo.instances);
+    for inst in &instances {
+        typecheck(cfo, inst);
+    }
+    cfo.instances = instances;
+}
+
+// the types of the retry params are checked with the rest of the params, this
+// checks the values
+fn typecheck_retry_params(cfo: &mut CompiledCheckFileOut, inst: &ChkInstance) {
+    let params = &inst.actual_params;
+    if !params.contains_key(RETRIES) && !params.contains_key(RETRY_FOR) {
+        cfo.add_error(
+            cfo.filename.clone(),
+            inst.content_address.clone(),
+            format!(
+                "Retrying check {} needs a {} or {} param",
+                inst.fn_def.name, RETRIES, RETRY_FOR
+            ),
+        );
+    }
+
+    for name in [RETRIES, RETRY_BACKOFF] {
+        if let Some(p) = params.get(name) {
+            if p.is_type(&ChkParamType::PkInt) && p.get_int() < 1 {
+                cfo.add_error(
+                    cfo.filename.clone(),
+                    p.content_address.clone(),
+                    format!("{} must be at least 1 for check {}", name, inst.fn_def.name),
+                );
+            }
+        }
+    }
+}
+
+// a wait group checks it's children every interval until wait_for runs out, so
+// interval can't be 0 or longer than wait_for
+fn typecheck_wait_params(cfo: &mut CompiledCheckFileOut, inst: &ChkInstance) {
+    let duration = |name: &str| {
+        inst.actual_params
+            .get(name)
+            .filter(|p| p.is_type(&ChkParamType::PkDuration))
+    };
+    let (Some(wait_for), Some(interval)) = (duration(WAIT_FOR), duration(WAIT_INTERVAL)) else {
+        return;
+    };
+
+    let msg = if interval.get_duration().duration.is_zero() {
+        format!("{} must be greater than 0", WAIT_INTERVAL)
+    } else if wait_for.get_duration().duration < interval.get_duration().duration {
+        format!("{} must be at least as long as {}", WAIT_FOR, WAIT_INTERVAL)
+    } else {
+        return;
+    };
+    cfo.add_error(
+        cfo.filename.clone(),
+        interval.content_address.clone(),
+        format!(
+            "Invalid {} for check {}: {}",
+            WAIT_INTERVAL, inst.fn_def.name, msg
+        ),
+    );
+}
+
 pub fn compile_checks_to_asts<'chkdef>(
     fns: &'chkdef ChkDefRegistry,
     all_ast_file_checks: Vec<AstFileChecks>,
@@ -164,10 +415,11 @@ pub fn compile_checks_to_asts<'chkdef>(
 
     for ast_file_checks in all_ast_file_checks {
         let mut cfo = CompiledCheckFileOut::new(ast_file_checks.filename.clone());
+        let file_scope = VarScope::default().with_vars(&mut cfo, &ast_file_checks.vars);
         let maybe_insts: Vec<_> = ast_file_checks
             .check_defs
             .into_iter()
-            .map(|ast_check_def| make_check_instance(&mut cfo, fns, ast_check_def))
+            .map(|ast_check_def| make_check_instances(&mut cfo, fns, ast_check_def, &file_scope))
             .collect();
 
         if maybe_insts.iter().any(|inst| inst.is_none()) {
@@ -185,8 +437,8 @@ pub fn compile_checks_to_asts<'chkdef>(
 
         let insts: Vec<ChkInstance<'chkdef>> = maybe_insts
             .into_iter()
-            .map(|maybe_inst| {
-                let mut i = maybe_inst.unwrap();
+            .flat_map(|maybe_insts| maybe_insts.unwrap())
+            .map(|mut i| {
                 i.materialize_formal_params();
                 i
             })
@@ -210,11 +462,12 @@ pub fn make_tools(
         for tool_def in ast_file_tool.tool_defs {
             let mut instance_params: Vec<ChkFormalParam> = vec![];
             let template_params = tool_def.params.template_params;
-            // process each $foo parameter. It needs to have a "type" field and a "required" field
+            // process each $foo parameter. It needs to have a "type" field and a "required" field,
+            // and may have a "default" field
             for (k, map) in tool_def.params.instance_params {
                 let unknown_keys: Vec<_> = map
                     .keys()
-                    .filter(|k| *k != "type" && *k != "required")
+                    .filter(|k| *k != "type" && *k != "required" && *k != "default")
                     .collect();
 
                 if !unknown_keys.is_empty() {
@@ -273,6 +526,44 @@ pub fn make_tools(
                     valid_prop = false;
                 }
 
+                if valid_prop {
+                    if let Some(default) = map.get("default") {
+                        let param_type =
+                            ChkParamType::from(map.get("type").unwrap().get_named_type());
+                        // a Path param accepts a String default, same as a check instance
+                        let valid_default = if param_type == ChkParamType::PkPath {
+                            default.is_coercible_to_path()
+                        } else {
+                            default.is_type(&param_type)
+                        };
+                        if !valid_default {
+                            compile_errors.push(CompileError {
+                                filename: ast_file_tool.filename.clone(),
+                                message: format!(
+                                    "Invalid default value for param '{}'. Got type {}, but expected type {}",
+                                    k,
+                                    default.type_name(),
+                                    param_type.type_name()
+                                ),
+                                content_address: default.content_address.clone(),
+                                error_type: CompilerErrorType::Error,
+                            });
+                            valid_prop = false;
+                        } else if map.get("required").unwrap().get_bool() {
+                            compile_errors.push(CompileError {
+                                filename: ast_file_tool.filename.clone(),
+                                message: format!(
+                                    "Param '{}' is required, and can't have a default value",
+                                    k
+                                ),
+                                content_address: default.content_address.clone(),
+                                error_type: CompilerErrorType::Error,
+                            });
+                            valid_prop = false;
+                        }
+                    }
+                }
+
                 if valid_prop {
                     let type_name = map.get("type").unwrap();
                     let required = map.get("required").unwrap();
@@ -280,7 +571,7 @@ pub fn make_tools(
                         name: k,
                         required: required.get_bool(),
                         param_type: ChkParamType::from(type_name.get_named_type()),
-                        param_default: None,
+                        param_default: map.get("default").map(|d| d.value.clone()),
                     });
                 }
             }
@@ -294,6 +585,7 @@ pub fn make_tools(
                 instance_params,
                 shell: None,         // TODO
                 accepts_retry: true, // TODO
+                is_query: tool_def.is_query,
             };
             fns.register_fn(td.tool_name.clone(), metadef_tool(&td));
         }
@@ -304,11 +596,12 @@ pub fn make_tools(
 #[cfg(test)]
 mod tests {
     use crate::predikit::{
-        comp::ast::AstActualParams,
+        comp::{ast::AstActualParams, pkparser},
         data::{
-            instance::ChkResult,
+            instance::{ChkResult, RunEnv},
             params::{ChkActualParam, ChkActualParams},
-            ChkDef, FParamsBuilder,
+            tags::TagFilter,
+            ChkDef, FParamsBuilder, ParsedDuration,
         },
     };
 
@@ -350,6 +643,8 @@ mod tests {
                     result: Ok(true),
                     process_out: None,
                     children_results: None,
+                    skipped: false,
+                    wait_stats: None,
                 }
             },
         };
@@ -394,6 +689,8 @@ mod tests {
                     result: Ok(true),
                     process_out: None,
                     children_results: None,
+                    skipped: false,
+                    wait_stats: None,
                 }
             },
         };
@@ -418,9 +715,13 @@ mod tests {
             children: vec![],
             content_address: 0..0,
             is_group: false,
+            matcher: None,
+            vars: vec![],
+            for_loop: None,
+            guard: None,
         };
 
-        let result = make_check_instance(&mut ccfo, &fns, ast_check_def);
+        let result = make_check_instance(&mut ccfo, &fns, ast_check_def, &VarScope::default());
         assert!(result.is_some());
         assert_eq!(ccfo.errors.len(), 0);
     }
@@ -442,9 +743,13 @@ mod tests {
             children: vec![],
             content_address: 0..0,
             is_group: false,
+            matcher: None,
+            vars: vec![],
+            for_loop: None,
+            guard: None,
         };
 
-        let result = make_check_instance(&mut ccfo, &fns, ast_check_def);
+        let result = make_check_instance(&mut ccfo, &fns, ast_check_def, &VarScope::default());
         assert!(result.is_none());
         assert_eq!(ccfo.errors.len(), 1);
 
@@ -476,6 +781,10 @@ mod tests {
             children: vec![],
             content_address: 0..0,
             is_group: false,
+            matcher: None,
+            vars: vec![],
+            for_loop: None,
+            guard: None,
         };
 
         let mut group_actual_params = AstActualParams::new();
@@ -490,9 +799,13 @@ mod tests {
             children: vec![ast_check_def],
             content_address: 0..0,
             is_group: true,
+            matcher: None,
+            vars: vec![],
+            for_loop: None,
+            guard: None,
         };
 
-        let result = make_check_instance(&mut ccfo, &fns, all_check_def);
+        let result = make_check_instance(&mut ccfo, &fns, all_check_def, &VarScope::default());
         assert!(result.is_some());
         assert_eq!(ccfo.errors.len(), 0);
         let inst = result.unwrap();
@@ -530,9 +843,14 @@ mod tests {
             children: vec![],
             content_address: 0..0,
             is_group: false,
+            matcher: None,
+            vars: vec![],
+            for_loop: None,
+            guard: None,
         };
 
-        let inst = make_check_instance(&mut ccfo, &fns, ast_check_def).unwrap();
+        let inst =
+            make_check_instance(&mut ccfo, &fns, ast_check_def, &VarScope::default()).unwrap();
         typecheck_check_params(&mut ccfo, &inst);
         assert!(ccfo.errors.is_empty());
     }
@@ -557,9 +875,14 @@ mod tests {
             children: vec![],
             content_address: 0..0,
             is_group: false,
+            matcher: None,
+            vars: vec![],
+            for_loop: None,
+            guard: None,
         };
 
-        let inst = make_check_instance(&mut ccfo, &fns, ast_check_def).unwrap();
+        let inst =
+            make_check_instance(&mut ccfo, &fns, ast_check_def, &VarScope::default()).unwrap();
         typecheck_check_params(&mut ccfo, &inst);
         assert_eq!(2, ccfo.errors.len());
 
@@ -609,9 +932,14 @@ mod tests {
             children: vec![],
             content_address: 0..0,
             is_group: false,
+            matcher: None,
+            vars: vec![],
+            for_loop: None,
+            guard: None,
         };
 
-        let inst = make_check_instance(&mut ccfo, &fns, ast_check_def).unwrap();
+        let inst =
+            make_check_instance(&mut ccfo, &fns, ast_check_def, &VarScope::default()).unwrap();
         typecheck_check_params(&mut ccfo, &inst);
         println!("{:#?}", ccfo);
         assert_eq!(1, ccfo.errors.len());
@@ -650,9 +978,14 @@ mod tests {
             children: vec![],
             content_address: 0..0,
             is_group: false,
+            matcher: None,
+            vars: vec![],
+            for_loop: None,
+            guard: None,
         };
 
-        let inst = make_check_instance(&mut ccfo, &fns, ast_check_def).unwrap();
+        let inst =
+            make_check_instance(&mut ccfo, &fns, ast_check_def, &VarScope::default()).unwrap();
         typecheck_check_params(&mut ccfo, &inst);
         println!("{:#?}", ccfo);
         assert_eq!(2, ccfo.errors.len());
@@ -714,9 +1047,14 @@ mod tests {
             children: vec![],
             content_address: 0..0,
             is_group: false,
+            matcher: None,
+            vars: vec![],
+            for_loop: None,
+            guard: None,
         };
 
-        let inst = make_check_instance(&mut ccfo, &fns, ast_check_def).unwrap();
+        let inst =
+            make_check_instance(&mut ccfo, &fns, ast_check_def, &VarScope::default()).unwrap();
         typecheck_check_params(&mut ccfo, &inst);
         assert_eq!(1, ccfo.errors.len());
 
@@ -724,6 +1062,614 @@ mod tests {
             "Invalid parameter type for check for_testing_paths_only! param 'str_param1'. Got type Path, but expected type String".to_string());
     }
 
+    // parse, make tools and compile checks from a single source string
+    fn compile_source<'a>(
+        fns: &'a mut ChkDefRegistry,
+        source: &str,
+    ) -> (Vec<CompileError>, Vec<CompiledCheckFileOut<'a>>) {
+        let mut ast_file = pkparser::TopLevelParser::new().parse(source).unwrap();
+        ast_file.set_filename(Some("foo.pk".to_string()));
+        let tool_errors = make_tools(fns, vec![ast_file.tools]);
+        let cfos = compile_checks_to_asts(fns, vec![ast_file.checks]);
+        (tool_errors, cfos)
+    }
+
+    #[test]
+    fn test_typecheck_matchers() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (tool_errors, cfos) = compile_source(
+            &mut fns,
+            r##"
+            query version? {
+                cmd_template: "echo 1.0"
+            }
+            all {
+                test version? {} =~ r#^1\.0#
+                test version? {} > "1.0"
+            }
+            "##,
+        );
+        assert!(tool_errors.is_empty());
+        let cfo = cfos.first().unwrap();
+        let group = cfo.instances.first().unwrap();
+        assert!(group.children.iter().all(|c| c.is_query));
+
+        // ordering operators only work with ints
+        assert_eq!(1, cfo.errors.len());
+        assert_eq!(
+            "Invalid matcher for check version?: The > operator requires an Int value",
+            cfo.errors.first().unwrap().message
+        );
+    }
+
+    #[test]
+    fn test_typecheck_query_needs_matcher() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (tool_errors, cfos) = compile_source(
+            &mut fns,
+            r#"
+            query version? {
+                cmd_template: "echo 1.0"
+            }
+            tool is_true? {
+                cmd_template: "true"
+            }
+            test version? {}
+            test is_true? {} = "1.0"
+            test shell { cmd: "echo 1.0" } = "1.0"
+            "#,
+        );
+        assert!(tool_errors.is_empty());
+        let messages: Vec<_> = cfos
+            .first()
+            .unwrap()
+            .errors
+            .iter()
+            .map(|e| e.message.clone())
+            .collect();
+        assert_eq!(
+            vec![
+                "Query version? must be used with a matcher, ex: test version? { ... } = \"some value\"",
+                "is_true? is not a query and can't be used with a matcher",
+                "shell is not a query and can't be used with a matcher",
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_vars() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (_, cfos) = compile_source(
+            &mut fns,
+            r#"
+            vars {
+                base: p($HOME/app)
+                port: 8080
+            }
+            all {
+                let port: 9090
+                title: "app on {{port}}"
+                test exists? { path: "{{base}}/config.yml" }
+                test port_open? { port: "{{port}}" }
+            }
+            test port_open? { port: "{{port}}" }
+            "#,
+        );
+        let cfo = cfos.first().unwrap();
+        assert!(cfo.errors.is_empty());
+        let group = cfo.instances.first().unwrap();
+        assert_eq!(
+            "app on 9090",
+            group.actual_params.get("title").unwrap().get_string()
+        );
+        let exists = &group.children[0];
+        assert_eq!(
+            "$HOME/app/config.yml",
+            exists.actual_params.get("path").unwrap().get_raw_path()
+        );
+        // the group var shadows the file var, and the Int type carries through
+        let port_open = &group.children[1];
+        assert_eq!(9090, port_open.actual_params.get("port").unwrap().get_int());
+        let port_open = &cfo.instances[1];
+        assert_eq!(8080, port_open.actual_params.get("port").unwrap().get_int());
+    }
+
+    #[test]
+    fn test_undefined_var() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let source = r#"
+            all {
+                vars { env: "prod" }
+            }
+            test exists? { path: "/etc/{{env}}" }
+            "#;
+        let (_, cfos) = compile_source(&mut fns, source);
+        let errors = &cfos.first().unwrap().errors;
+        assert_eq!(1, errors.len());
+        assert_eq!("Undefined variable env", errors[0].message);
+        assert_eq!(
+            "path: \"/etc/{{env}}\"",
+            &source[errors[0].content_address.clone()]
+        );
+    }
+
+    #[test]
+    fn test_tool_param_defaults() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (tool_errors, cfos) = compile_source(
+            &mut fns,
+            r#"
+            query greeting? {
+                cmd_template: "echo {{greeting}} {{name}}"
+                $greeting {
+                    type: String
+                    required: false
+                    default: "hello"
+                }
+                $name {
+                    type: String
+                    required: true
+                }
+            }
+            test greeting? { name: "world" } = "hello world"
+            test greeting? { name: "world" greeting: "hi" } = "hi world"
+            "#,
+        );
+        assert!(tool_errors.is_empty());
+        let cfo = cfos.first().unwrap();
+        assert!(cfo.errors.is_empty());
+
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        for inst in &cfo.instances {
+            assert!(inst.run_check_maybe_retry(&run_env).is_check_pass());
+        }
+    }
+
+    #[test]
+    fn test_tool_param_invalid_defaults() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (tool_errors, _) = compile_source(
+            &mut fns,
+            r#"
+            tool bad_defaults? {
+                cmd_template: "true"
+                $count {
+                    type: Int
+                    required: false
+                    default: "ten"
+                }
+                $name {
+                    type: String
+                    required: true
+                    default: "foo"
+                }
+            }
+            "#,
+        );
+        let mut messages: Vec<_> = tool_errors.iter().map(|e| e.message.clone()).collect();
+        messages.sort();
+        assert_eq!(
+            vec![
+                "Invalid default value for param 'count'. Got type String, but expected type Int",
+                "Param 'name' is required, and can't have a default value",
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_tool_list_and_map_params() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (tool_errors, cfos) = compile_source(
+            &mut fns,
+            r#"
+            query joined? {
+                cmd_template: "echo {{#each ports}}{{this}} {{/each}}{{labels.env}}"
+                $ports {
+                    type: List(Int)
+                    required: true
+                }
+                $labels {
+                    type: Map(String)
+                    required: false
+                    default: { env: "dev" }
+                }
+            }
+            test joined? { ports: [22, 80] } = "22 80 dev"
+            test joined? { ports: [443] labels: { env: "prod" } } = "443 prod"
+            test joined? { ports: ["22"] }
+            "#,
+        );
+        assert!(tool_errors.is_empty());
+        let cfo = cfos.first().unwrap();
+        let messages: Vec<_> = cfo.errors.iter().map(|e| e.message.clone()).collect();
+        assert_eq!(
+            vec![
+                "Invalid parameter type for check joined? param 'ports'. Got type List(String), but expected type List(Int)",
+                "Query joined? must be used with a matcher, ex: test joined? { ... } = \"some value\"",
+            ],
+            messages
+        );
+
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        for inst in cfo.instances.iter().take(2) {
+            assert!(inst.run_check_maybe_retry(&run_env).is_check_pass());
+        }
+    }
+
+    #[test]
+    fn test_for_loops() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (_, cfos) = compile_source(
+            &mut fns,
+            r#"
+            vars { ports: [22, 80] }
+            for pkg in ["docker", "git"] {
+                test on_path? { path: pkg }
+            }
+            all {
+                for port in ports {
+                    for host in ["a", "b"] {
+                        test port_addr_open? { addr_port: "{{host}}:{{port}}" }
+                    }
+                }
+            }
+            "#,
+        );
+        let cfo = cfos.first().unwrap();
+        assert!(cfo.errors.is_empty());
+        assert_eq!(3, cfo.instances.len());
+
+        let paths: Vec<_> = cfo.instances[0..2]
+            .iter()
+            .map(|i| i.actual_params.get("path").unwrap().get_string())
+            .collect();
+        assert_eq!(vec!["docker", "git"], paths);
+        assert_ne!(cfo.instances[0].instance_id, cfo.instances[1].instance_id);
+        assert_eq!(
+            vec![(
+                "pkg".to_owned(),
+                ChkParamInternalValue::PkString("git".to_owned())
+            )],
+            cfo.instances[1].loop_vars
+        );
+
+        let group = &cfo.instances[2];
+        assert!(group.loop_vars.is_empty());
+        let addrs: Vec<_> = group
+            .children
+            .iter()
+            .map(|i| i.actual_params.get("addr_port").unwrap().get_string())
+            .collect();
+        assert_eq!(vec!["a:22", "b:22", "a:80", "b:80"], addrs);
+        assert_eq!(2, group.children[3].loop_vars.len());
+    }
+
+    #[test]
+    fn test_for_loop_errors() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (_, cfos) = compile_source(
+            &mut fns,
+            r#"
+            vars { port: 22 }
+            for p in port {
+                test on_path? { path: "sh" }
+            }
+            for p in nope {
+                test on_path? { path: "sh" }
+            }
+            for p in ["sh"] {
+                test on_path? { path: q }
+            }
+            "#,
+        );
+        let messages: Vec<_> = cfos
+            .first()
+            .unwrap()
+            .errors
+            .iter()
+            .map(|e| e.message.clone())
+            .collect();
+        assert_eq!(
+            vec![
+                "A for loop needs a List to iterate over, got type Int",
+                "Undefined variable nope",
+                "Undefined variable q",
+                "Child instance failed to compile",
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_when_guards() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (_, cfos) = compile_source(
+            &mut fns,
+            r#"
+            all {
+                when: test exists? { path: "/etc/arch-release" }
+                test on_path? {
+                    when: "test -f /etc/debian_version"
+                    path: "apt"
+                }
+            }
+            "#,
+        );
+        let cfo = cfos.first().unwrap();
+        assert!(cfo.errors.is_empty());
+        let group = cfo.instances.first().unwrap();
+        assert_eq!("exists?", group.guard.as_ref().unwrap().fn_def.name);
+        let check = group.children.first().unwrap();
+        assert!(check.guard.is_none());
+        assert_eq!(
+            "test -f /etc/debian_version",
+            check.actual_params.get("when").unwrap().get_string()
+        );
+    }
+
+    #[test]
+    fn test_threshold_groups() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (_, cfos) = compile_source(
+            &mut fns,
+            r#"
+            at_least(1) {
+                test on_path? { path: "sh" }
+                test on_path? { path: "ls" }
+            }
+            exactly(3) {
+                test on_path? { path: "sh" }
+                test on_path? { path: "ls" }
+            }
+            at_most(-1) {
+                test on_path? { path: "sh" }
+            }
+            "#,
+        );
+        let cfo = cfos.first().unwrap();
+        let messages: Vec<_> = cfo.errors.iter().map(|e| e.message.clone()).collect();
+        assert_eq!(
+            vec![
+                "Invalid n for group exactly(3), n must be between 0 and the number of children (2)",
+                "Invalid n for group at_most(-1), n must be between 0 and the number of children (1)",
+            ],
+            messages
+        );
+        let group = cfo.instances.first().unwrap();
+        assert_eq!("at_least", group.fn_def.name);
+        assert_eq!(1, group.actual_params.get("n").unwrap().get_int());
+    }
+
+    #[test]
+    fn test_pruned_threshold_groups() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (_, mut cfos) = compile_source(
+            &mut fns,
+            r#"
+            at_least(2) {
+                test on_path? { path: "sh" tags: ["local"] }
+                test on_path? { path: "ls" tags: ["local"] }
+                test port_open? { port: 22 tags: ["net"] }
+            }
+            "#,
+        );
+        let cfo = cfos.first_mut().unwrap();
+        assert!(cfo.errors.is_empty());
+
+        let instances = std::mem::take(&mut cfo.instances);
+        cfo.instances = TagFilter::new(vec![], vec!["net".to_owned()]).prune(instances);
+        typecheck_pruned_thresholds(cfo);
+        assert!(cfo.errors.is_empty());
+
+        // only one child is selected, so at_least(2) can never pass
+        let (_, mut cfos) = compile_source(
+            &mut fns,
+            r#"
+            at_least(2) {
+                test on_path? { path: "sh" tags: ["local"] }
+                test port_open? { port: 22 tags: ["net"] }
+            }
+            "#,
+        );
+        let cfo = cfos.first_mut().unwrap();
+        let instances = std::mem::take(&mut cfo.instances);
+        cfo.instances = TagFilter::new(vec!["net".to_owned()], vec![]).prune(instances);
+        typecheck_pruned_thresholds(cfo);
+        let messages: Vec<_> = cfo.errors.iter().map(|e| e.message.clone()).collect();
+        assert_eq!(
+            vec!["Invalid n for group at_least(2), only 1 of it's children are selected by --tags and --exclude-tags"],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_retry_params() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (_, cfos) = compile_source(
+            &mut fns,
+            r#"
+            @test exists? {
+                path: "/tmp"
+                retry_delay: d(1s)
+                retry_for: d(5m)
+                retry_backoff: 2
+                retry_max_delay: d(30s)
+                retry_jitter: d(500ms)
+            }
+            @test exists? {
+                path: "/tmp"
+                retry_delay: d(1s)
+            }
+            @test exists? {
+                path: "/tmp"
+                retries: 0
+                retry_delay: d(1s)
+                retry_backoff: 0
+            }
+            "#,
+        );
+        let mut messages: Vec<_> = cfos
+            .first()
+            .unwrap()
+            .errors
+            .iter()
+            .map(|e| e.message.clone())
+            .collect();
+        messages.sort();
+        assert_eq!(
+            vec![
+                "Retrying check exists? needs a retries or retry_for param",
+                "retries must be at least 1 for check exists?",
+                "retry_backoff must be at least 1 for check exists?",
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_tags_param() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (_, cfos) = compile_source(
+            &mut fns,
+            r#"
+            all {
+                tags: ["net"]
+                test on_path? { path: "sh" tags: ["local", "fast"] }
+                test on_path? { path: "sh" tags: "slow" }
+            }
+            "#,
+        );
+        let cfo = cfos.first().unwrap();
+        let messages: Vec<_> = cfo.errors.iter().map(|e| e.message.clone()).collect();
+        assert_eq!(
+            vec!["Invalid parameter type for check on_path? param 'tags'. Got type String, but expected type List(String)"],
+            messages
+        );
+        let group = cfo.instances.first().unwrap();
+        assert_eq!(vec!["net"], group.tags());
+        assert_eq!(vec!["local", "fast"], group.children[0].tags());
+    }
+
+    #[test]
+    fn test_timeout_param() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (_, cfos) = compile_source(
+            &mut fns,
+            r#"
+            test shell { cmd: "sleep 1" timeout: d(5s) }
+            test shell { cmd: "sleep 1" timeout: "5s" }
+            wait until_pass wait_for: d(1m) interval: d(1s) {
+                test on_path? { path: "sh" }
+            }
+            "#,
+        );
+        let cfo = cfos.first().unwrap();
+        let messages: Vec<_> = cfo.errors.iter().map(|e| e.message.clone()).collect();
+        assert_eq!(
+            vec!["Invalid parameter type for check shell param 'timeout'. Got type String, but expected type Duration"],
+            messages
+        );
+
+        let mut run_env = RunEnv::default();
+        let shell = cfo.instances.first().unwrap();
+        let wait = cfo.instances.last().unwrap();
+        assert_eq!(
+            Some(std::time::Duration::from_secs(5)),
+            shell.process_timeout(&run_env)
+        );
+        assert_eq!(None, wait.process_timeout(&run_env));
+
+        let global_timeout =
+            ParsedDuration::new(std::time::Duration::from_secs(30), "30s".to_owned());
+        run_env.global_config.insert(
+            "timeout".to_owned(),
+            ChkActualParam::new_duration("timeout".to_owned(), global_timeout, 0..0),
+        );
+        assert_eq!(
+            Some(std::time::Duration::from_secs(5)),
+            shell.process_timeout(&run_env)
+        );
+        assert_eq!(
+            Some(std::time::Duration::from_secs(30)),
+            wait.process_timeout(&run_env)
+        );
+    }
+
+    #[test]
+    fn test_wait_params() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (_, cfos) = compile_source(
+            &mut fns,
+            r#"
+            wait until_pass wait_for: d(1m) interval: d(0s) {
+                test on_path? { path: "sh" }
+            }
+            wait until_pass wait_for: d(1s) interval: d(2s) {
+                test on_path? { path: "sh" }
+            }
+            wait until_pass wait_for: d(1m) interval: d(1s) {
+                timeout: d(5s)
+                test on_path? { path: "sh" }
+            }
+            "#,
+        );
+        let cfo = cfos.first().unwrap();
+        let messages: Vec<_> = cfo.errors.iter().map(|e| e.message.clone()).collect();
+        assert_eq!(
+            vec![
+                "Invalid interval for check wait_until_pass: interval must be greater than 0",
+                "Invalid interval for check wait_until_pass: wait_for must be at least as long as interval",
+            ],
+            messages
+        );
+
+        // timeout is the meta param, like on any other check
+        let wait = cfo.instances.last().unwrap();
+        assert_eq!(
+            Some(std::time::Duration::from_secs(5)),
+            wait.process_timeout(&RunEnv::default())
+        );
+    }
+
+    #[test]
+    fn test_when_guard_errors() {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let (_, cfos) = compile_source(
+            &mut fns,
+            r#"
+            test on_path? {
+                when: "true"
+                when: test exists? { }
+                path: "sh"
+            }
+            "#,
+        );
+        let messages: Vec<_> = cfos
+            .first()
+            .unwrap()
+            .errors
+            .iter()
+            .map(|e| e.message.clone())
+            .collect();
+        assert_eq!(
+            vec![
+                "Check on_path? can't have both a when: command and a when: check",
+                "Required parameter is missing for check exists?: path",
+            ],
+            messages
+        );
+    }
+
     #[test]
     fn test_compile_checks_to_asts() {
         // TODO
@@ -30,8 +30,10 @@ pub fn show_fancy_error(
         ]);
 
     let writer = StandardStream::stderr(ColorChoice::Always);
-    let mut config = codespan_reporting::term::Config::default();
-    config.display_style = DisplayStyle::Rich;
+    let config = codespan_reporting::term::Config {
+        display_style: DisplayStyle::Rich,
+        ..Default::default()
+    };
 
     codespan_reporting::term::emit(&mut writer.lock(), &config, &files, &diag).unwrap();
 }
@@ -0,0 +1,71 @@
+// Copyright (c) 2025 Dave Parfitt
+
+use std::path::{Path, PathBuf};
+
+/// Resolve the path in an `include "..."` statement to a sorted list of files.
+/// The path is relative to the directory of the including file, and may contain
+/// glob characters, ex: `include "lib/*.pk"`
+pub fn resolve_include(including_file: &Path, include_path: &str) -> Result<Vec<PathBuf>, String> {
+    let include = Path::new(include_path);
+    let full_path = if include.is_absolute() {
+        include.to_path_buf()
+    } else {
+        let parent = including_file.parent().unwrap_or_else(|| Path::new(""));
+        // the directory of the including file may contain glob characters too
+        let escaped_parent = glob::Pattern::escape(&parent.to_string_lossy());
+        PathBuf::from(escaped_parent).join(include)
+    };
+
+    let pattern = full_path.to_string_lossy();
+    let entries = glob::glob(&pattern)
+        .map_err(|e| format!("Invalid include pattern {}: {}", include_path, e))?;
+
+    let mut paths: Vec<PathBuf> = vec![];
+    for entry in entries {
+        match entry {
+            Ok(path) => {
+                if path.is_file() {
+                    paths.push(path);
+                }
+            }
+            Err(e) => return Err(format!("Can't read include {}: {}", include_path, e)),
+        }
+    }
+
+    if paths.is_empty() {
+        return Err(format!("No files found for include {}", include_path));
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir, File};
+
+    #[test]
+    fn test_resolve_include() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let lib_dir = tmp_dir.path().join("lib");
+        create_dir(&lib_dir).unwrap();
+        File::create(lib_dir.join("b.pk")).unwrap();
+        File::create(lib_dir.join("a.pk")).unwrap();
+        File::create(lib_dir.join("notes.txt")).unwrap();
+        let main = tmp_dir.path().join("main.pk");
+
+        let paths = resolve_include(&main, "lib/*.pk").unwrap();
+        assert_eq!(vec![lib_dir.join("a.pk"), lib_dir.join("b.pk")], paths);
+
+        let paths = resolve_include(&main, "lib/b.pk").unwrap();
+        assert_eq!(vec![lib_dir.join("b.pk")], paths);
+
+        // relative to the including file, not the current directory
+        let nested = lib_dir.join("a.pk");
+        let paths = resolve_include(&nested, "b.pk").unwrap();
+        assert_eq!(vec![lib_dir.join("b.pk")], paths);
+
+        assert!(resolve_include(&main, "missing/*.pk").is_err());
+        assert!(resolve_include(&main, "lib/[.pk").is_err());
+    }
+}
@@ -3,6 +3,7 @@
 use log::debug;
 use std::num::ParseIntError;
 
+use crate::predikit::data::params::NamedType;
 use crate::predikit::data::{instance::ContentAddress, ParsedDuration};
 
 #[derive(Debug, Clone, PartialEq)]
@@ -23,7 +24,7 @@ impl From<ParseIntError> for LexicalError {
 
 // remove first and last double quote character
 // since it's coming right from the regex, the first and last double quotes should really be there :-)
-pub fn strip_quotes(s: &String) -> String {
+pub fn strip_quotes(s: &str) -> String {
     s.strip_prefix('"')
         .unwrap()
         .strip_suffix('"')
@@ -31,7 +32,16 @@ pub fn strip_quotes(s: &String) -> String {
         .to_owned()
 }
 
-pub fn strip_parens_and_trim(s: &String) -> String {
+// remove the r# prefix and # suffix from a regex literal
+pub fn strip_regex(s: &str) -> String {
+    s.strip_prefix("r#")
+        .unwrap()
+        .strip_suffix('#')
+        .unwrap()
+        .to_owned()
+}
+
+pub fn strip_parens_and_trim(s: &str) -> String {
     s.strip_prefix("(")
         .unwrap()
         .strip_suffix(')')
@@ -40,9 +50,9 @@ pub fn strip_parens_and_trim(s: &String) -> String {
         .to_owned()
 }
 
-pub fn parse_duration_str(s: &String) -> Option<ParsedDuration> {
-    match duration_str::parse(s.clone()) {
-        Ok(dur) => Some(ParsedDuration::new(dur, s.clone())),
+pub fn parse_duration_str(s: &str) -> Option<ParsedDuration> {
+    match duration_str::parse(s) {
+        Ok(dur) => Some(ParsedDuration::new(dur, s.to_owned())),
         Err(e) => {
             debug!("Duration parse error:\n{}", e);
             None
@@ -50,7 +60,23 @@ pub fn parse_duration_str(s: &String) -> Option<ParsedDuration> {
     }
 }
 
-pub fn parse_path_str(s: &String) -> Option<String> {
+// item_type is only used by List and Map, ex: List(Int)
+pub fn parse_named_type(type_name: &str, item_type: Option<&str>) -> Option<NamedType> {
+    match (type_name, item_type) {
+        ("String", None) => Some(NamedType::PtnString),
+        ("Int", None) => Some(NamedType::PtnInt),
+        ("Bool", None) => Some(NamedType::PtnBool),
+        ("Duration", None) => Some(NamedType::PtnDuration),
+        ("Path", None) => Some(NamedType::PtnPath),
+        ("List", Some(item)) => {
+            parse_named_type(item, None).map(|t| NamedType::PtnList(Box::new(t)))
+        }
+        ("Map", Some(item)) => parse_named_type(item, None).map(|t| NamedType::PtnMap(Box::new(t))),
+        _ => None,
+    }
+}
+
+pub fn parse_path_str(s: &str) -> Option<String> {
     if s.is_empty() {
         None
     } else {
@@ -64,14 +90,9 @@ mod test {
 
     #[test]
     fn test_str_stripping() {
-        assert_eq!(
-            "test".to_string(),
-            super::strip_parens_and_trim(&"(test)".to_string())
-        );
-        assert_eq!(
-            "test".to_string(),
-            super::strip_quotes(&"\"test\"".to_string())
-        );
+        assert_eq!("test".to_string(), super::strip_parens_and_trim("(test)"));
+        assert_eq!("test".to_string(), super::strip_quotes("\"test\""));
+        assert_eq!(r"^1\.0.*".to_string(), super::strip_regex(r"r#^1\.0.*#"));
     }
 
     #[test]
@@ -91,11 +112,31 @@ mod test {
     #[test]
     fn test_keywords() {
         let _ = pkparser::PktToolParser::new().parse("tool").unwrap();
+        let _ = pkparser::PktQueryParser::new().parse("query").unwrap();
+        let _ = pkparser::PktIncludeParser::new().parse("include").unwrap();
+        let _ = pkparser::PktVarsParser::new().parse("vars").unwrap();
+        let _ = pkparser::PktLetParser::new().parse("let").unwrap();
+        let _ = pkparser::PktForParser::new().parse("for").unwrap();
+        let _ = pkparser::PktInParser::new().parse("in").unwrap();
+        let _ = pkparser::PktWhenParser::new().parse("when").unwrap();
+        let _ = pkparser::PktWaitParser::new().parse("wait").unwrap();
+        let _ = pkparser::PktUntilPassParser::new()
+            .parse("until_pass")
+            .unwrap();
+        let _ = pkparser::PktUntilFailParser::new()
+            .parse("until_fail")
+            .unwrap();
+        let _ = pkparser::PktUntilErrorParser::new()
+            .parse("until_error")
+            .unwrap();
         let _ = pkparser::PktNotParser::new().parse("not").unwrap();
         let _ = pkparser::PktTestParser::new().parse("test").unwrap();
         let _ = pkparser::PktAllParser::new().parse("all").unwrap();
         let _ = pkparser::PktAnyParser::new().parse("any").unwrap();
         let _ = pkparser::PktNoneParser::new().parse("none").unwrap();
+        let _ = pkparser::PktAtLeastParser::new().parse("at_least").unwrap();
+        let _ = pkparser::PktAtMostParser::new().parse("at_most").unwrap();
+        let _ = pkparser::PktExactlyParser::new().parse("exactly").unwrap();
         let _ = pkparser::PktBoolParser::new().parse("true").unwrap();
         let _ = pkparser::PktBoolParser::new().parse("false").unwrap();
         let _ = pkparser::PktRetryingParser::new().parse("@").unwrap();
@@ -0,0 +1,279 @@
+// Copyright (c) 2025 Dave Parfitt
+
+use super::CompiledCheckFileOut;
+use crate::predikit::data::instance::ChkLoopVars;
+use crate::predikit::data::params::{ChkActualParam, ChkParamInternalValue};
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+// ex: "{{base}}/config.yml"
+static VAR_REF: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{\s*([a-zA-Z_][a-zA-Z0-9_]*)\s*\}\}").unwrap());
+
+/// Variables defined with `vars { ... }` or `let` at file or group scope.
+/// A group gets a child scope of it's parent, so inner definitions shadow outer ones.
+#[derive(Debug, Clone, Default)]
+pub struct VarScope {
+    vars: HashMap<String, ChkActualParam>,
+    // vars bound by enclosing for loops, outermost loop first
+    loop_vars: ChkLoopVars,
+}
+
+impl VarScope {
+    /// Create a child scope containing `vars`. Each var can reference any var defined
+    /// before it. Errors are added to the cfo, and the var that caused it is skipped.
+    pub fn with_vars(&self, cfo: &mut CompiledCheckFileOut, vars: &[ChkActualParam]) -> VarScope {
+        let mut scope = self.clone();
+        let mut defined_here: Vec<&str> = vec![];
+        for var in vars {
+            if defined_here.contains(&var.name.as_str()) {
+                cfo.add_error(
+                    cfo.filename.clone(),
+                    var.content_address.clone(),
+                    format!("Variable {} is already defined in this scope", var.name),
+                );
+                continue;
+            }
+            defined_here.push(&var.name);
+
+            match scope.substitute(var) {
+                Ok(value) => {
+                    scope.vars.insert(var.name.clone(), value);
+                }
+                Err(msg) => cfo.add_error(cfo.filename.clone(), var.content_address.clone(), msg),
+            }
+        }
+        scope
+    }
+
+    /// Create a child scope for a single iteration of a for loop
+    pub fn with_loop_var(&self, var: ChkActualParam) -> VarScope {
+        let mut scope = self.clone();
+        scope.loop_vars.push((var.name.clone(), var.value.clone()));
+        scope.vars.insert(var.name.clone(), var);
+        scope
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ChkActualParam> {
+        self.vars.get(name)
+    }
+
+    pub fn loop_vars(&self) -> &ChkLoopVars {
+        &self.loop_vars
+    }
+
+    /// Replace a bare var reference (`pkg_name: pkg`) with the value of the var, or
+    /// replace `{{var}}` references in a String or Path param.
+    /// If the entire value is a single reference, the param takes on the type of the var,
+    /// so `port: "{{port}}"` is an Int if port is an Int. Otherwise the value of each var is
+    /// interpolated into the string, and the result is a Path if a Path var was used.
+    pub fn substitute(&self, param: &ChkActualParam) -> Result<ChkActualParam, String> {
+        let (s, is_path) = match &param.value {
+            ChkParamInternalValue::PkVarRef(var_name) => {
+                let var = self.lookup(var_name)?;
+                return Ok(ChkActualParam {
+                    name: param.name.clone(),
+                    value: var.value.clone(),
+                    content_address: param.content_address.clone(),
+                });
+            }
+            ChkParamInternalValue::PkString(s) => (s, false),
+            ChkParamInternalValue::PkPath(p) => (p, true),
+            _ => return Ok(param.clone()),
+        };
+
+        if !VAR_REF.is_match(s) {
+            return Ok(param.clone());
+        }
+
+        if let Some(caps) = VAR_REF.captures(s) {
+            if caps.get(0).unwrap().as_str() == s {
+                let var = self.lookup(&caps[1])?;
+                return Ok(ChkActualParam {
+                    name: param.name.clone(),
+                    value: var.value.clone(),
+                    content_address: param.content_address.clone(),
+                });
+            }
+        }
+
+        let mut is_path = is_path;
+        let mut error: Option<String> = None;
+        let interpolated = VAR_REF.replace_all(s, |caps: &Captures| {
+            let var = match self.lookup(&caps[1]) {
+                Ok(var) => var,
+                Err(msg) => {
+                    error.get_or_insert(msg);
+                    return String::new();
+                }
+            };
+            match &var.value {
+                ChkParamInternalValue::PkPath(_) => is_path = true,
+                ChkParamInternalValue::PkTypeName(_) => {
+                    error.get_or_insert(format!(
+                        "Variable {} is a {} and can't be used inside a string",
+                        var.name,
+                        var.type_name()
+                    ));
+                }
+                _ => (),
+            }
+            var.value_as_string()
+        });
+
+        if let Some(msg) = error {
+            return Err(msg);
+        }
+
+        let value = if is_path {
+            ChkParamInternalValue::PkPath(interpolated.to_string())
+        } else {
+            ChkParamInternalValue::PkString(interpolated.to_string())
+        };
+        Ok(ChkActualParam {
+            name: param.name.clone(),
+            value,
+            content_address: param.content_address.clone(),
+        })
+    }
+
+    /// Replace `{{var}}` references to defined vars in a String param, and leave any
+    /// other reference as is. Used for hooks, which are rendered again when they run
+    /// with values that aren't known at compile time, ex: `on_fail: "echo {{stderr}}"`.
+    pub fn substitute_defined(&self, param: &ChkActualParam) -> ChkActualParam {
+        let ChkParamInternalValue::PkString(s) = &param.value else {
+            return param.clone();
+        };
+        let interpolated =
+            VAR_REF.replace_all(s, |caps: &Captures| match self.vars.get(&caps[1]) {
+                Some(var) if !matches!(var.value, ChkParamInternalValue::PkTypeName(_)) => {
+                    var.value_as_string()
+                }
+                _ => caps[0].to_owned(),
+            });
+        ChkActualParam {
+            name: param.name.clone(),
+            value: ChkParamInternalValue::PkString(interpolated.to_string()),
+            content_address: param.content_address.clone(),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<&ChkActualParam, String> {
+        self.vars
+            .get(name)
+            .ok_or_else(|| format!("Undefined variable {}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string_param(name: &str, s: &str) -> ChkActualParam {
+        ChkActualParam::new_string(name.to_owned(), s.to_owned(), 0..0)
+    }
+
+    #[test]
+    fn test_substitute() {
+        let mut cfo = CompiledCheckFileOut::new(None);
+        let scope = VarScope::default().with_vars(
+            &mut cfo,
+            &[
+                ChkActualParam::new_path("base".to_owned(), "$HOME/app".to_owned(), 0..0),
+                ChkActualParam::new_int("port".to_owned(), 8080, 0..0),
+                string_param("host", "localhost"),
+                string_param("addr", "{{host}}:{{ port }}"),
+            ],
+        );
+        assert!(cfo.errors.is_empty());
+
+        // a lone reference keeps the type of the var
+        let p = scope.substitute(&string_param("port", "{{port}}")).unwrap();
+        assert_eq!(ChkParamInternalValue::PkInt(8080), p.value);
+
+        // interpolating a path makes a path, env vars are expanded at runtime
+        let p = scope
+            .substitute(&string_param("path", "{{base}}/config.yml"))
+            .unwrap();
+        assert_eq!(
+            ChkParamInternalValue::PkPath("$HOME/app/config.yml".to_owned()),
+            p.value
+        );
+
+        let p = scope.substitute(&string_param("addr", "{{addr}}")).unwrap();
+        assert_eq!(
+            ChkParamInternalValue::PkString("localhost:8080".to_owned()),
+            p.value
+        );
+
+        let p = scope
+            .substitute(&string_param("s", "no vars here"))
+            .unwrap();
+        assert_eq!(
+            ChkParamInternalValue::PkString("no vars here".to_owned()),
+            p.value
+        );
+
+        assert_eq!(
+            Err("Undefined variable nope".to_owned()),
+            scope
+                .substitute(&string_param("s", "x {{nope}}"))
+                .map(|p| p.value)
+        );
+    }
+
+    #[test]
+    fn test_substitute_defined() {
+        let scope = VarScope::default().with_loop_var(string_param("pkg", "docker"));
+        let p = scope.substitute_defined(&string_param(
+            "on_fail",
+            "echo {{pkg}} failed: {{ stderr }}",
+        ));
+        assert_eq!(
+            ChkParamInternalValue::PkString("echo docker failed: {{ stderr }}".to_owned()),
+            p.value
+        );
+    }
+
+    #[test]
+    fn test_loop_vars() {
+        let scope = VarScope::default()
+            .with_loop_var(string_param("pkg", "docker"))
+            .with_loop_var(ChkActualParam::new_int("port".to_owned(), 22, 0..0));
+        let p = scope
+            .substitute(&ChkActualParam::new_var_ref(
+                "pkg_name".to_owned(),
+                "pkg".to_owned(),
+                0..0,
+            ))
+            .unwrap();
+        assert_eq!("docker", p.get_string());
+        assert_eq!(
+            &vec![
+                (
+                    "pkg".to_owned(),
+                    ChkParamInternalValue::PkString("docker".to_owned())
+                ),
+                ("port".to_owned(), ChkParamInternalValue::PkInt(22)),
+            ],
+            scope.loop_vars()
+        );
+    }
+
+    #[test]
+    fn test_scopes() {
+        let mut cfo = CompiledCheckFileOut::new(None);
+        let outer = VarScope::default().with_vars(&mut cfo, &[string_param("env", "dev")]);
+        let inner = outer.with_vars(&mut cfo, &[string_param("env", "prod")]);
+        assert_eq!("dev", outer.get("env").unwrap().get_string());
+        assert_eq!("prod", inner.get("env").unwrap().get_string());
+        assert!(cfo.errors.is_empty());
+
+        let _ = VarScope::default().with_vars(
+            &mut cfo,
+            &[string_param("env", "dev"), string_param("env", "prod")],
+        );
+        assert_eq!(1, cfo.errors.len());
+    }
+}
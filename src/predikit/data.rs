@@ -1,18 +1,25 @@
 // Copyright (c) 2025 Dave Parfitt
 
+pub mod bus;
+pub mod dryrun;
 pub mod events;
 pub mod instance;
+pub mod jobs;
+pub mod matchers;
 pub mod params;
+pub mod process;
+pub mod retry;
+pub mod tags;
 pub mod tools;
 
-use crate::predikit::functions::builtin;
+use crate::predikit::functions::{builtin, waiting};
 use instance::{ChkInstance, ChkResult, RunEnv};
 use params::{ChkActualParam, ChkActualParams, ChkParamInternalValue};
 use std::collections::HashMap;
 use std::fmt;
 
 type ChkFunctionParams = HashMap<String, ChkActualParam>;
-type ChkFn = fn(&RunEnv, &ChkFunctionParams, &ChkInstance) -> ChkResult;
+pub type ChkFn = fn(&RunEnv, &ChkFunctionParams, &ChkInstance) -> ChkResult;
 
 // for convenience!
 pub type ChkFormalParams = HashMap<String, ChkFormalParam>;
@@ -40,6 +47,8 @@ pub enum ChkParamType {
     PkTypeName,
     PkDuration,
     PkPath,
+    PkList(Box<ChkParamType>),
+    PkMap(Box<ChkParamType>),
 }
 
 impl ChkParamType {
@@ -49,15 +58,19 @@ impl ChkParamType {
     const TYPE_NAME_TYPE: &'static str = "Typename";
     const DURATION_TYPE: &'static str = "Duration";
     const PATH_TYPE: &'static str = "Path";
+    const LIST_TYPE: &'static str = "List";
+    const MAP_TYPE: &'static str = "Map";
 
-    pub fn type_name(&self) -> &'static str {
+    pub fn type_name(&self) -> String {
         match self {
-            ChkParamType::PkString => Self::STRING_TYPE,
-            ChkParamType::PkInt => Self::INT_TYPE,
-            ChkParamType::PkBool => Self::BOOL_TYPE,
-            ChkParamType::PkTypeName => Self::TYPE_NAME_TYPE,
-            ChkParamType::PkDuration => Self::DURATION_TYPE,
-            ChkParamType::PkPath => Self::PATH_TYPE,
+            ChkParamType::PkString => Self::STRING_TYPE.to_owned(),
+            ChkParamType::PkInt => Self::INT_TYPE.to_owned(),
+            ChkParamType::PkBool => Self::BOOL_TYPE.to_owned(),
+            ChkParamType::PkTypeName => Self::TYPE_NAME_TYPE.to_owned(),
+            ChkParamType::PkDuration => Self::DURATION_TYPE.to_owned(),
+            ChkParamType::PkPath => Self::PATH_TYPE.to_owned(),
+            ChkParamType::PkList(t) => format!("{}({})", Self::LIST_TYPE, t.type_name()),
+            ChkParamType::PkMap(t) => format!("{}({})", Self::MAP_TYPE, t.type_name()),
         }
     }
 }
@@ -331,6 +344,9 @@ impl ChkDefRegistry {
         for agg_def in builtin::define_aggs() {
             reg.register_group_fn(agg_def.name.clone(), agg_def);
         }
+        for wait_def in waiting::define_waits() {
+            reg.register_group_fn(wait_def.name.clone(), wait_def);
+        }
 
         reg
     }
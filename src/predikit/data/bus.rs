@@ -0,0 +1,103 @@
+// Copyright (c) 2025 Dave Parfitt
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use super::events::ChkLifecycleEvent;
+
+/// Broadcasts the events of a run to any number of subscribers, ex: a formatter for the
+/// console and another for a report file. Each subscriber runs on it's own thread with
+/// it's own receiver, and gets a copy of every event.
+#[derive(Default)]
+pub struct ChkEventBus {
+    subscribers: Vec<Sender<ChkLifecycleEvent>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+/// Waits for the subscribers of a bus to finish
+pub struct ChkEventBusHandle {
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl ChkEventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run a subscriber on it's own thread. It gets every event sent to the bus, and
+    /// it's receiver is closed once the bus shuts down.
+    pub fn subscribe<F>(&mut self, subscriber: F)
+    where
+        F: FnOnce(Receiver<ChkLifecycleEvent>) + Send + 'static,
+    {
+        let (tx, rx) = channel();
+        self.subscribers.push(tx);
+        self.threads.push(thread::spawn(move || subscriber(rx)));
+    }
+
+    /// Start sending events to the subscribers. The bus shuts down once every clone of
+    /// the returned sender has been dropped, ex: when the RunEnv it was given to is dropped.
+    pub fn start(self) -> (Sender<ChkLifecycleEvent>, ChkEventBusHandle) {
+        let (tx, rx) = channel::<ChkLifecycleEvent>();
+        let mut subscribers = self.subscribers;
+        let mut threads = self.threads;
+        threads.push(thread::spawn(move || {
+            for event in rx.iter() {
+                // a subscriber that has stopped listening doesn't get any more events
+                subscribers.retain(|s| s.send(event.clone()).is_ok());
+            }
+            // dropping the senders closes each subscriber's receiver
+        }));
+        (tx, ChkEventBusHandle { threads })
+    }
+}
+
+impl ChkEventBusHandle {
+    pub fn join(self) {
+        for t in self.threads {
+            t.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_event_bus() {
+        let seen: Arc<Mutex<Vec<String>>> = Arc::default();
+        let mut bus = ChkEventBus::new();
+        for name in ["a", "b"] {
+            let seen = seen.clone();
+            bus.subscribe(move |rx| {
+                for event in rx.iter() {
+                    seen.lock().unwrap().push(format!("{} {:?}", name, event));
+                }
+            });
+        }
+        // a subscriber that stops early doesn't stop the others
+        bus.subscribe(|rx| {
+            let _ = rx.recv();
+        });
+
+        let (tx, handle) = bus.start();
+        tx.send(ChkLifecycleEvent::CheckStart(1)).unwrap();
+        tx.send(ChkLifecycleEvent::CheckPass(1)).unwrap();
+        drop(tx);
+        handle.join();
+
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort();
+        assert_eq!(
+            vec![
+                "a CheckPass(1)",
+                "a CheckStart(1)",
+                "b CheckPass(1)",
+                "b CheckStart(1)",
+            ],
+            seen
+        );
+    }
+}
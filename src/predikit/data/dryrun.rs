@@ -0,0 +1,136 @@
+// Copyright (c) 2025 Dave Parfitt
+
+use super::instance::{ChkInstance, HOOKS, TITLE, WHEN};
+use super::params::ChkParamInternalValue;
+use crate::predikit::functions::builtin_fs::{SHELL, SHELL_CMD};
+use crate::predikit::functions::builtin_tools::{render_cmd_template, render_hook_template};
+
+/// Describe the commands that would run for a tree of check instances, without running
+/// anything: the rendered cmd_template of tools, shell commands, `when:` commands and
+/// hooks, and the expanded value of p() paths. One line per item, in tree order.
+pub fn dry_run_lines(instances: &[ChkInstance]) -> Vec<String> {
+    let mut lines = vec![];
+    for inst in instances {
+        describe(inst, "", &mut lines);
+    }
+    lines
+}
+
+fn describe(inst: &ChkInstance, prefix: &str, lines: &mut Vec<String>) {
+    let title = inst
+        .actual_params
+        .get(TITLE)
+        .map(|t| format!("[{}] ", t.get_string()))
+        .unwrap_or_default();
+    let negate = if inst.negated { "not " } else { "" };
+    let segment = if inst.fn_def.is_group { "|  " } else { "->" };
+    lines.push(format!(
+        "{}{} {}{}[{}]",
+        prefix, segment, title, negate, inst.fn_def.name
+    ));
+
+    // details are lined up under the check name
+    let detail_prefix = format!("{}|      ", prefix);
+    let mut detail = |s: String| lines.push(format!("{}{}", detail_prefix, s));
+
+    if let Some(cmd) = inst.actual_params.get(WHEN) {
+        detail(format!("when: {}", cmd.get_string()));
+    }
+
+    if inst.fn_def.template_params.is_some() {
+        match render_cmd_template(inst, &inst.actual_params) {
+            Ok(cmd) => detail(format!("cmd: {}", cmd)),
+            Err(e) => detail(format!("cmd: <{}>", e)),
+        }
+    } else if inst.fn_def.name == SHELL {
+        if let Some(cmd) = inst.actual_params.get(SHELL_CMD) {
+            detail(format!("cmd: {}", cmd.get_string()));
+        }
+    }
+
+    let mut paths: Vec<_> = inst
+        .actual_params
+        .iter()
+        .filter(|(_, p)| matches!(p.value, ChkParamInternalValue::PkPath(_)))
+        .collect();
+    paths.sort_by_key(|(name, _)| name.as_str());
+    for (name, p) in paths {
+        match p.get_path() {
+            Ok(expanded) => detail(format!("{}: p({}) => {}", name, p.get_raw_path(), expanded)),
+            Err(e) => detail(format!("{}: p({}) => <{}>", name, p.get_raw_path(), e)),
+        }
+    }
+
+    // hooks are rendered the way on_init sees them, before the check has a result
+    let (hook_data, _) = inst.hook_data(1, None);
+    for hook in HOOKS {
+        if let Some(cmd) = inst.actual_params.get(hook) {
+            match render_hook_template(cmd.get_string(), &hook_data) {
+                Ok(cmd) => detail(format!("{}: {}", hook, cmd)),
+                Err(e) => detail(format!("{}: <{}>", hook, e)),
+            }
+        }
+    }
+
+    if let Some(guard) = &inst.guard {
+        detail("when:".to_owned());
+        describe(guard, &detail_prefix, lines);
+    }
+
+    let child_prefix = format!("{}|  ", prefix);
+    for child in &inst.children {
+        describe(child, &child_prefix, lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predikit::comp::compiler::{compile_checks_to_asts, make_tools};
+    use crate::predikit::comp::pkparser;
+    use crate::predikit::data::ChkDefRegistry;
+
+    #[test]
+    fn test_dry_run_lines() {
+        let source = r#"
+            tool pkg_installed? {
+                cmd_template: "pacman -Qi {{pkg}}"
+                $pkg {
+                    type: String
+                    required: true
+                }
+            }
+            all {
+                title: "Host"
+                test pkg_installed? { pkg: "docker" on_fail: "echo {{pkg}} missing" }
+                test shell {
+                    cmd: "touch /tmp/foo"
+                    when: "test -d /tmp"
+                }
+                test exists? { path: p($PREDIKIT_DRY_RUN_TEST/foo) }
+            }
+        "#;
+        std::env::set_var("PREDIKIT_DRY_RUN_TEST", "/opt");
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let ast_file = pkparser::TopLevelParser::new().parse(source).unwrap();
+        assert!(make_tools(&mut fns, vec![ast_file.tools]).is_empty());
+        let cfos = compile_checks_to_asts(&fns, vec![ast_file.checks]);
+        let cfo = cfos.first().unwrap();
+        assert!(cfo.errors.is_empty());
+
+        assert_eq!(
+            vec![
+                "|   [Host] [all]",
+                "|  -> [pkg_installed?]",
+                "|  |      cmd: pacman -Qi docker",
+                "|  |      on_fail: echo 'docker' missing",
+                "|  -> [shell]",
+                "|  |      when: test -d /tmp",
+                "|  |      cmd: touch /tmp/foo",
+                "|  -> [exists?]",
+                "|  |      path: p($PREDIKIT_DRY_RUN_TEST/foo) => /opt/foo",
+            ],
+            dry_run_lines(&cfo.instances)
+        );
+    }
+}
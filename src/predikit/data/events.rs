@@ -1,6 +1,7 @@
 // Copyright (c) 2025 Dave Parfitt
 
-use crate::predikit::data::instance::{ChkInstId, ChkInstance};
+use crate::predikit::data::instance::{ChkInstId, ChkInstance, ChkLoopVars, ChkProcessOut};
+use crate::predikit::data::matchers::ChkMatcher;
 use crate::predikit::data::params::ChkActualParam;
 use crate::predikit::data::{ChkFormalParam, ChkResult, RunEnv};
 use std::collections::HashMap;
@@ -8,19 +9,36 @@ use std::collections::HashMap;
 use super::ParsedDuration;
 
 /// Events that are sent to the events layer via an event emitter.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ChkLifecycleEvent {
     Init(ChkDescMap, Option<String>), // filename // TODO: this is super expensive for large amounts of checks
     Term(Option<String>),             // filename
-    //AllStart,
-    //AllFinish,
-    CheckRetry(ChkInstId, u64),                 // attempt #
-    CheckRetrySleep(ChkInstId, ParsedDuration), // sleep in seconds
+    AllStart,                         // before the first file
+    AllFinish(std::time::Duration),   // after the last file, with the wall time of the run
+    CheckRetry(ChkInstId, u64),       // attempt #
+    CheckRetrySleep(ChkInstId, ParsedDuration), // the actual sleep, including backoff and jitter
     CheckStart(ChkInstId),
     CheckPass(ChkInstId),
     CheckFail(ChkInstId),
-    CheckError(ChkInstId),
+    CheckError(ChkInstId, String),         // why the check errored
+    CheckOutput(ChkInstId, ChkProcessOut), // sent before the result of a check that ran a command
+    CheckSkip(ChkInstId),                  // the check's when: guard didn't pass
+    CheckWaitTick(ChkInstId, u64, std::time::Duration, std::time::Duration), // iteration, elapsed, timeout
     CheckFinish(ChkInstId, std::time::Duration),
+    HookStart(ChkInstId, String, String), // hook name, command
+    HookFinish(ChkInstId, ChkHookRun),
+}
+
+/// The output of an on_* hook. Hooks don't print anything themselves, formatters
+/// decide how to show them.
+#[derive(Debug, Clone)]
+pub struct ChkHookRun {
+    pub hook_name: String,
+    pub cmd: String,
+    pub process_out: ChkProcessOut,
+    pub duration: std::time::Duration,
+    // why the hook failed: a non-zero exit code, a timeout, or the command couldn't run
+    pub error: Option<String>,
 }
 
 /// A scope used for timing a check. Emits Pass, Fail, or Error events to the emitter.
@@ -47,6 +65,16 @@ impl<'a> ChkEventScope<'a> {
     pub fn emit_result(&self, r: &ChkResult) {
         // emit an error event if r is an error, otherwise emit pass or fail
         // depending on the result
+        if r.is_check_skipped() {
+            self.run_env.emit(ChkLifecycleEvent::CheckSkip(self.chk_id));
+            return;
+        }
+        if let Some(process_out) = &r.process_out {
+            self.run_env.emit(ChkLifecycleEvent::CheckOutput(
+                self.chk_id,
+                process_out.clone(),
+            ));
+        }
         match &r.result {
             Ok(bool_result) => {
                 if *bool_result {
@@ -55,9 +83,9 @@ impl<'a> ChkEventScope<'a> {
                     self.run_env.emit(ChkLifecycleEvent::CheckFail(self.chk_id));
                 }
             }
-            Err(_) => {
+            Err(e) => {
                 self.run_env
-                    .emit(ChkLifecycleEvent::CheckError(self.chk_id));
+                    .emit(ChkLifecycleEvent::CheckError(self.chk_id, e.clone()));
             }
         }
     }
@@ -75,7 +103,7 @@ impl Drop for ChkEventScope<'_> {
 
 /// A description of a check instance combined with its definition and parameters, suitable
 /// for passing to the events layer for output (console or otherwise).
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChkDesc {
     pub instance_id: usize,
     pub fn_desc: ChkFnDesc,
@@ -89,6 +117,8 @@ pub struct ChkDesc {
     pub title: Option<String>,
     pub is_root: bool, // is this check at the top level of a check file?
     pub result: Option<ChkDescResult>,
+    pub matcher: Option<ChkMatcher>,
+    pub loop_vars: ChkLoopVars,
 }
 
 impl ChkDesc {
@@ -97,15 +127,16 @@ impl ChkDesc {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ChkDescResult {
     Pass,
     Fail,
     Error,
+    Skipped,
 }
 
 /// A description of a check function, including its name and formal parameters.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChkFnDesc {
     pub fn_name: String,
     pub formal_params: HashMap<String, ChkFormalParam>,
@@ -141,6 +172,8 @@ pub fn desc_from_instances(instances: &Vec<ChkInstance>) -> ChkDescMap {
             title: i.title.clone(),
             is_root,
             result: None,
+            matcher: i.matcher.clone(),
+            loop_vars: i.loop_vars.clone(),
         };
         v.insert(i.instance_id, this);
     }
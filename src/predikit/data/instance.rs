@@ -1,17 +1,30 @@
 // Copyright (c) 2025 Dave Parfitt
 
-use crate::predikit::data::events::{ChkEventScope, ChkLifecycleEvent};
+use crate::predikit::data::events::{ChkEventScope, ChkHookRun, ChkLifecycleEvent};
 use crate::predikit::data::params::ChkActualParam;
-use crate::predikit::data::{ChkDef, ChkFormalParam};
+use crate::predikit::data::{ChkDef, ChkFormalParam, ChkParamType};
+use crate::predikit::functions::builtin_tools::{render_hook_template, template_data};
+use crate::predikit::functions::waiting::ChkWaitStats;
 use log::debug;
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{fmt, thread};
 
-use super::params::ChkActualParams;
+use super::jobs::ChkJobSlots;
+use super::matchers::ChkMatcher;
+use super::params::{ChkActualParams, ChkParamInternalValue};
+use super::process::{run_shell, run_shell_with_env, TIMEOUT};
+use super::retry::{
+    ChkRetryPolicy, RETRIES, RETRY_BACKOFF, RETRY_DELAY, RETRY_FOR, RETRY_JITTER, RETRY_MAX_DELAY,
+};
 use super::{ChkFormalParams, FParamBuilder, ParsedDuration};
 
 pub type ContentAddress = std::ops::Range<usize>;
 
+// The vars bound by the for loops an instance was expanded from, outermost loop first
+pub type ChkLoopVars = Vec<(String, ChkParamInternalValue)>;
+
 pub struct ChkInstancePreMaterialized<'a> {
     pub title: Option<String>,
     pub fn_def: Option<&'a ChkDef>,
@@ -23,6 +36,9 @@ pub struct ChkInstancePreMaterialized<'a> {
     pub instance_id: usize,
     pub content_address: ContentAddress,
     pub is_query: bool,
+    pub matcher: Option<ChkMatcher>,
+    pub loop_vars: ChkLoopVars,
+    pub guard: Option<Box<ChkInstance<'a>>>,
 }
 
 #[derive(Clone, Debug)]
@@ -37,6 +53,10 @@ pub struct ChkInstance<'chkdef> {
     pub instance_id: usize,
     pub content_address: ContentAddress,
     pub is_query: bool,
+    pub matcher: Option<ChkMatcher>,
+    pub loop_vars: ChkLoopVars,
+    // a check that must pass before this check runs, set via `when:`
+    pub guard: Option<Box<ChkInstance<'chkdef>>>,
 }
 
 impl<'a> From<ChkInstancePreMaterialized<'a>> for ChkInstance<'a> {
@@ -52,6 +72,9 @@ impl<'a> From<ChkInstancePreMaterialized<'a>> for ChkInstance<'a> {
             instance_id: inst2.instance_id,
             content_address: inst2.content_address,
             is_query: inst2.is_query,
+            matcher: inst2.matcher,
+            loop_vars: inst2.loop_vars,
+            guard: inst2.guard,
         }
     }
 }
@@ -70,15 +93,49 @@ impl fmt::Display for ChkInstance<'_> {
     }
 }
 
-const TITLE: &str = "title";
+pub const TITLE: &str = "title";
 const HOOK_ON_PASS: &str = "on_pass";
 const HOOK_ON_FAIL: &str = "on_fail";
 const HOOK_ON_ERROR: &str = "on_error";
 const HOOK_ON_INIT: &str = "on_init";
 const HOOK_ON_TERM: &str = "on_term";
+pub const WHEN: &str = "when";
+const TAGS: &str = "tags";
+const EXPECT_ERROR: &str = "expect_error";
+
+// the names hooks can use in their templates, ex: on_fail: "echo {{stderr}}"
+// and as env vars, ex: $PREDIKIT_STDERR
+const HOOK_DATA_CHECK: &str = "check";
+const HOOK_DATA_ATTEMPT: &str = "attempt";
+const HOOK_DATA_RESULT: &str = "result";
+const HOOK_DATA_ERROR: &str = "error";
+const HOOK_DATA_STDOUT: &str = "stdout";
+const HOOK_DATA_STDERR: &str = "stderr";
+const HOOK_DATA_EXIT_CODE: &str = "exit_code";
+
+// strings are passed as is, everything else (ex: a list) as json. null is empty
+fn template_value_as_env(v: &serde_json::Value) -> String {
+    match v {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+pub const HOOKS: [&str; 5] = [
+    HOOK_ON_INIT,
+    HOOK_ON_PASS,
+    HOOK_ON_FAIL,
+    HOOK_ON_ERROR,
+    HOOK_ON_TERM,
+];
 
-const META_PARAMS: [&str; 6] = [
+const META_PARAMS: [&str; 10] = [
     TITLE,
+    WHEN,
+    TAGS,
+    EXPECT_ERROR,
+    TIMEOUT,
     HOOK_ON_PASS,
     HOOK_ON_FAIL,
     HOOK_ON_ERROR,
@@ -90,160 +147,451 @@ impl ChkInstance<'_> {
     pub fn materialize_formal_params(&mut self) {
         let mut fps = self.fn_def.formal_params.clone();
         fn build_param(name: &str) -> ChkFormalParam {
-            FParamBuilder::new(name).pk_string().not_required().build()
+            // tags are a list, ex: tags: ["net", "slow"]. Everything else is a string,
+            // a flag or a duration
+            let param_type = match name {
+                TAGS => ChkParamType::PkList(Box::new(ChkParamType::PkString)),
+                EXPECT_ERROR => ChkParamType::PkBool,
+                TIMEOUT => ChkParamType::PkDuration,
+                _ => ChkParamType::PkString,
+            };
+            FParamBuilder::new(name)
+                .param_type(param_type)
+                .not_required()
+                .build()
         }
 
+        // a check's own params win over a meta param with the same name,
+        // ex: a tool with a $timeout param
         for mp in META_PARAMS {
-            fps.insert(mp.to_owned(), build_param(mp));
+            fps.entry(mp.to_owned()).or_insert_with(|| build_param(mp));
         }
 
-        // add retrying paramaters
+        // add retrying paramaters. retries or retry_for (or both) must be set,
+        // the compiler checks that
         if self.is_retrying {
-            // TODO: default param values!
-            let retries = FParamBuilder::new("retries").pk_int().required().build();
-            fps.insert("retries".to_owned(), retries);
-
-            let retry_delay = FParamBuilder::new("retry_delay")
+            let retry_delay = FParamBuilder::new(RETRY_DELAY)
                 .pk_duration()
                 .required()
                 .build();
-            fps.insert("retry_delay".to_owned(), retry_delay);
+            fps.insert(RETRY_DELAY.to_owned(), retry_delay);
+
+            for name in [RETRIES, RETRY_BACKOFF] {
+                let p = FParamBuilder::new(name).pk_int().not_required().build();
+                fps.insert(name.to_owned(), p);
+            }
+            for name in [RETRY_MAX_DELAY, RETRY_JITTER, RETRY_FOR] {
+                let p = FParamBuilder::new(name)
+                    .pk_duration()
+                    .not_required()
+                    .build();
+                fps.insert(name.to_owned(), p);
+            }
         }
         self.materialized_formal_params = Some(fps);
     }
 
-    fn run_hook_if_defined(&self, hook_name: &str) {
-        if let Some(hook_value) = self.actual_params.get(hook_name) {
-            let hook_value = hook_value.get_string();
-            //println!("Running check {}->[{}]", hook_name, hook_value);
-            let cmd_result = std::process::Command::new("sh")
-                .arg("-c")
-                .arg(hook_value)
-                .output();
-            if let Ok(out) = cmd_result {
-                // TODO: address unwraps
-                println!(
-                    "[{}]: {}",
-                    hook_name,
-                    String::from_utf8(out.stdout).unwrap()
-                );
-                // TODO: what to do about stdout?
-                //   it would be cool to be able to set all on_* shell params globalls
-                //    ex: set the shell for all on_* hooks to /bin/bash -c
-                //
-                // println!(
-                //     "[{}]: {}",
-                //     hook_name,
-                //     String::from_utf8(out.stderr).unwrap()
-                // );
-            } else {
-                // TODO: what to do here?
-                println!("Error running {} hook command", hook_name);
-            }
+    // the tags set on this check, not including tags inherited from parent groups
+    pub fn tags(&self) -> Vec<String> {
+        self.actual_params
+            .get(TAGS)
+            .map(|tags| {
+                tags.get_list()
+                    .iter()
+                    .map(|t| t.value_as_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The time limit for processes started by this check (the check itself, its hooks
+    /// and its `when:` command). The check's `timeout` param wins over the global default.
+    /// A tool that defines a `timeout` param of its own only uses the default.
+    pub fn process_timeout(&self, run_env: &RunEnv) -> Option<Duration> {
+        let own_timeout = if self.fn_def.formal_params.contains_key(TIMEOUT) {
+            None
         } else {
+            self.actual_params.get(TIMEOUT)
+        };
+        own_timeout
+            .or_else(|| run_env.global_config.get(TIMEOUT))
+            .map(|p| p.get_duration().duration)
+    }
+
+    // Hooks are rendered with the params of the check (like a tool's cmd_template), and
+    // what's known about the run so far: the attempt number, the result and the output
+    // of the check. on_init runs before the check, so it has no result or output.
+    // Values are single quoted when they're rendered, see render_hook_template. The same
+    // values are exported to the hook as PREDIKIT_* env vars, which is the safest way to
+    // use output in a shell command.
+    pub fn hook_data(
+        &self,
+        attempt: u64,
+        result: Option<&ChkResult>,
+    ) -> (HashMap<String, serde_json::Value>, Vec<(String, String)>) {
+        let mut data = template_data(self, &self.actual_params);
+        data.retain(|name, _| !HOOKS.contains(&name.as_str()));
+        let mut env: Vec<(String, String)> = data
+            .iter()
+            .map(|(name, v)| {
+                (
+                    format!("PREDIKIT_PARAM_{}", name.to_uppercase()),
+                    template_value_as_env(v),
+                )
+            })
+            .collect();
+
+        let process_out = result.and_then(|r| r.process_out.as_ref());
+        let run_data = [
+            (HOOK_DATA_CHECK, self.fn_def.name.clone().into()),
+            (HOOK_DATA_ATTEMPT, attempt.into()),
+            (
+                HOOK_DATA_RESULT,
+                result.map_or(serde_json::Value::Null, |r| r.result_name().into()),
+            ),
+            (
+                HOOK_DATA_ERROR,
+                result
+                    .and_then(|r| r.result.as_ref().err())
+                    .map_or(serde_json::Value::Null, |e| e.clone().into()),
+            ),
+            (
+                HOOK_DATA_STDOUT,
+                process_out
+                    .and_then(|po| po.stdout.clone())
+                    .map_or(serde_json::Value::Null, |o| o.into()),
+            ),
+            (
+                HOOK_DATA_STDERR,
+                process_out
+                    .and_then(|po| po.stderr.clone())
+                    .map_or(serde_json::Value::Null, |o| o.into()),
+            ),
+            (
+                HOOK_DATA_EXIT_CODE,
+                process_out
+                    .and_then(|po| po.exit_code)
+                    .map_or(serde_json::Value::Null, |c| c.into()),
+            ),
+        ];
+        // the run data wins over a param with the same name
+        for (name, value) in run_data {
+            env.push((
+                format!("PREDIKIT_{}", name.to_uppercase()),
+                template_value_as_env(&value),
+            ));
+            data.insert(name.to_owned(), value);
+        }
+        (data, env)
+    }
+
+    // Hook output is sent to the formatters in a HookFinish event. Ok if the hook
+    // isn't defined or exits with 0, otherwise Err with why the hook failed.
+    fn run_hook_if_defined(
+        &self,
+        run_env: &RunEnv,
+        hook_name: &str,
+        attempt: u64,
+        result: Option<&ChkResult>,
+    ) -> Result<(), String> {
+        let Some(hook_value) = self.actual_params.get(hook_name) else {
             debug!(
                 "Hook {} is not defined for this check {}",
                 hook_name, self.fn_def.name
             );
+            return Ok(());
+        };
+        let (data, env) = self.hook_data(attempt, result);
+        let rendered = render_hook_template(hook_value.get_string(), &data);
+        let cmd = rendered.as_deref().unwrap_or(hook_value.get_string());
+        run_env.emit(ChkLifecycleEvent::HookStart(
+            self.instance_id,
+            hook_name.to_owned(),
+            cmd.to_owned(),
+        ));
+
+        let start = Instant::now();
+        let shell_out = match &rendered {
+            Ok(cmd) => run_shell_with_env(cmd, self.process_timeout(run_env), &env)
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.clone()),
+        };
+        let (process_out, error) = match shell_out {
+            Ok(out) => {
+                let error = match (out.timed_out, out.process_out.exit_code) {
+                    (Some(t), _) => {
+                        Some(format!("timed out after {}", humantime::format_duration(t)))
+                    }
+                    (None, _) if out.success => None,
+                    (None, Some(code)) => Some(format!("exited with code {}", code)),
+                    (None, None) => Some("was killed by a signal".to_owned()),
+                };
+                (out.process_out, error)
+            }
+            Err(e) => (ChkProcessOut::default(), Some(e)),
+        };
+        run_env.emit(ChkLifecycleEvent::HookFinish(
+            self.instance_id,
+            ChkHookRun {
+                hook_name: hook_name.to_owned(),
+                cmd: cmd.to_owned(),
+                process_out,
+                duration: start.elapsed(),
+                error: error.clone(),
+            },
+        ));
+
+        match error {
+            Some(e) => Err(format!("{} hook {}", hook_name, e)),
+            None => Ok(()),
         }
     }
 
-    fn sleep_and_emit_retry_events(
-        &self,
-        run_env: &RunEnv,
-        retry_delay: &ParsedDuration,
-        attempt_num: u64,
-    ) {
-        let duration = retry_delay.duration;
+    fn sleep_and_emit_retry_events(&self, run_env: &RunEnv, sleep: Duration, attempt_num: u64) {
+        // with backoff and jitter, the sleep is usually not the retry_delay that was
+        // written in the check, so report the actual sleep (rounded to ms for display)
+        let sleep_str = humantime::format_duration(Duration::from_millis(sleep.as_millis() as u64));
         run_env.emit(ChkLifecycleEvent::CheckRetrySleep(
             self.instance_id,
-            retry_delay.clone(),
+            ParsedDuration::new(sleep, sleep_str.to_string()),
         ));
 
-        thread::sleep(duration);
+        thread::sleep(sleep);
         run_env.emit(ChkLifecycleEvent::CheckRetry(self.instance_id, attempt_num));
     }
 
-    fn eval_result_for_hook(&self, r: &ChkResult) {
+    fn eval_result_for_hook(&self, run_env: &RunEnv, attempt: u64, r: &ChkResult) {
         //println!("EVAL HOOK: {:#?}", r);
-        if r.is_check_pass() {
-            self.run_hook_if_defined(HOOK_ON_PASS);
+        let hook_name = if r.is_check_pass() {
+            HOOK_ON_PASS
         } else if r.is_check_fail() {
-            self.run_hook_if_defined(HOOK_ON_FAIL);
+            HOOK_ON_FAIL
         } else if r.is_check_error() {
-            self.run_hook_if_defined(HOOK_ON_ERROR);
+            HOOK_ON_ERROR
+        } else if r.is_check_skipped() {
+            // a group with only skipped children doesn't run any result hooks
+            debug!("Check {} was skipped", self.fn_def.name);
+            return;
         } else {
             panic!("Unknown check state");
-        }
+        };
+        // the result of the check is already known, a failing result hook only
+        // shows up in it's HookFinish event
+        let _ = self.run_hook_if_defined(run_env, hook_name, attempt, Some(r));
     }
 
+    // A failing on_init hook makes the check an error without running it
     fn run_check_no_retry(&self, run_env: &RunEnv) -> ChkResult {
-        self.run_hook_if_defined(HOOK_ON_INIT);
-        let r = self.exec(run_env);
-        self.eval_result_for_hook(&r);
-        self.run_hook_if_defined(HOOK_ON_TERM);
+        let r = match self.run_hook_if_defined(run_env, HOOK_ON_INIT, 1, None) {
+            Ok(()) => self.exec(run_env),
+            Err(e) => self.report_error(run_env, e),
+        };
+        self.eval_result_for_hook(run_env, 1, &r);
+        let _ = self.run_hook_if_defined(run_env, HOOK_ON_TERM, 1, Some(&r));
+        r
+    }
+
+    // Ok(true) if the check should run. A `when:` command must exit with 0,
+    // and a `when:` check must pass. Guard checks run without emitting events.
+    fn eval_guard(&self, run_env: &RunEnv) -> Result<bool, String> {
+        if let Some(guard) = &self.guard {
+            let guard_env = RunEnv {
+                emitter: None,
+                global_config: run_env.global_config.clone(),
+                job_slots: run_env.job_slots.clone(),
+            };
+            let guard_result = guard.run_check_maybe_retry(&guard_env);
+            return guard_result
+                .result
+                .map(|r| r && !guard_result.skipped)
+                .map_err(|e| format!("Error running when: check for {}: {}", self.fn_def.name, e));
+        }
+
+        if let Some(cmd) = self.actual_params.get(WHEN) {
+            let cmd = cmd.get_string();
+            debug!("Running when: command [{}]", cmd);
+            return run_shell(cmd, self.process_timeout(run_env))
+                .map(|out| out.success)
+                .map_err(|e| {
+                    format!(
+                        "Error running when: command for {}: {}",
+                        self.fn_def.name, e
+                    )
+                });
+        }
+
+        Ok(true)
+    }
+
+    /// Report this check as skipped without running it, ex: a check after a short
+    /// circuit in a group, or after a failure with --fail-fast. The children of a
+    /// skipped group didn't run either, so they're reported as skipped too.
+    pub fn report_skipped(&self, run_env: &RunEnv) -> ChkResult {
+        let chk_scope = run_env.new_check_scope(self.instance_id);
+        for child in &self.children {
+            child.report_skipped(run_env);
+        }
+        let r = ChkResult::skipped();
+        chk_scope.emit_result(&r);
+        r
+    }
+
+    // Report this check as an error without running it, ex: a when: check that errors
+    fn report_error(&self, run_env: &RunEnv, msg: String) -> ChkResult {
+        let chk_scope = run_env.new_check_scope(self.instance_id);
+        let r = ChkResult {
+            result: Err(msg),
+            process_out: None,
+            children_results: None,
+            skipped: false,
+            wait_stats: None,
+        };
+        chk_scope.emit_result(&r);
         r
     }
 
     pub fn run_check_maybe_retry(&self, run_env: &RunEnv) -> ChkResult {
+        match self.eval_guard(run_env) {
+            Ok(true) => (),
+            Ok(false) => return self.report_skipped(run_env),
+            Err(e) => return self.report_error(run_env, e),
+        }
+
         if !self.is_retrying {
             return self.run_check_no_retry(run_env);
         }
 
-        // retries doesn't have a default, so it's safe to unwrap
-        // as the compiler will enforce that retries is required.
-        // at least for now!
-        // TODO: won't this fail if someone tries a negative retries value?
-        let retries = self.actual_params.get("retries").unwrap().get_int() as u64;
-        // TODO: should probably not convert to u64 and just support 32 bit numbers for retries..?
-        // TODO: won't this fail if someone tries a negative retry_delay?
-        let retry_delay = self
-            .actual_params
-            .get("retry_delay")
-            .unwrap()
-            .get_duration();
+        let policy = ChkRetryPolicy::from_params(&self.actual_params);
+        let started = Instant::now();
 
-        self.run_hook_if_defined(HOOK_ON_INIT);
+        if let Err(e) = self.run_hook_if_defined(run_env, HOOK_ON_INIT, 1, None) {
+            let r = self.report_error(run_env, e);
+            self.eval_result_for_hook(run_env, 1, &r);
+            let _ = self.run_hook_if_defined(run_env, HOOK_ON_TERM, 1, Some(&r));
+            return r;
+        }
 
-        // I need the last value returned, which is annoying in rust
-        for attempt_num in 1..=retries {
+        let mut attempt_num = 1;
+        loop {
             let attempt_result = self.exec(run_env);
+            self.eval_result_for_hook(run_env, attempt_num, &attempt_result);
 
-            if self.is_query {
-                // possibly modify the attempt_result here
-                // query todo:
-                // expressions ( > 1 && < 5 ) ? not sure if it's worth it
-                println!("Process is_query predicate");
-            }
-
-            self.eval_result_for_hook(&attempt_result);
-            if attempt_num == retries || attempt_result.is_check_pass() {
-                self.eval_result_for_hook(&attempt_result);
-                self.run_hook_if_defined(HOOK_ON_TERM);
-                return attempt_result;
+            // a retrying group where every child was skipped won't change on the next attempt
+            let sleep = if attempt_result.is_check_pass() || attempt_result.is_check_skipped() {
+                None
             } else {
-                self.sleep_and_emit_retry_events(run_env, &retry_delay, attempt_num);
-                continue;
+                policy.next_sleep(attempt_num, started)
+            };
+
+            match sleep {
+                Some(sleep) => {
+                    self.sleep_and_emit_retry_events(run_env, sleep, attempt_num);
+                    attempt_num += 1;
+                }
+                None => {
+                    let _ = self.run_hook_if_defined(
+                        run_env,
+                        HOOK_ON_TERM,
+                        attempt_num,
+                        Some(&attempt_result),
+                    );
+                    return attempt_result;
+                }
             }
         }
-        unreachable!();
+    }
+
+    // A query produces a value (its trimmed stdout) instead of a pass/fail, and the
+    // matcher decides if the check passes.
+    fn apply_matcher(&self, check_run: ChkResult) -> ChkResult {
+        let matcher = match &self.matcher {
+            Some(m) => m,
+            None => return check_run,
+        };
+
+        let result = match &check_run.result {
+            Err(e) => Err(e.clone()),
+            Ok(false) => Err(format!(
+                "Query {} failed, there is no value to match against (exit code: {})",
+                self.fn_def.name,
+                check_run
+                    .process_out
+                    .as_ref()
+                    .and_then(|po| po.exit_code)
+                    .map_or("none".to_owned(), |c| c.to_string())
+            )),
+            Ok(true) => match check_run
+                .process_out
+                .as_ref()
+                .and_then(|po| po.stdout.as_ref())
+            {
+                Some(stdout) => {
+                    debug!("Matching query output [{}] {}", stdout.trim(), matcher);
+                    matcher.matches(stdout)
+                }
+                None => Err(format!(
+                    "Check {} doesn't produce any output to match against",
+                    self.fn_def.name
+                )),
+            },
+        };
+
+        ChkResult {
+            result,
+            process_out: check_run.process_out,
+            children_results: check_run.children_results,
+            skipped: false,
+            wait_stats: None,
+        }
+    }
+
+    // With `expect_error: true`, a check passes only if it errors. This is applied
+    // before negation, so `not` + `expect_error` passes for anything but an error.
+    fn apply_expect_error(&self, check_run: ChkResult) -> ChkResult {
+        let expect_error = self
+            .actual_params
+            .get(EXPECT_ERROR)
+            .is_some_and(|p| p.get_bool());
+        if !expect_error || check_run.is_check_skipped() {
+            return check_run;
+        }
+
+        if let Err(e) = &check_run.result {
+            debug!("Check {} errored as expected: {}", self.fn_def.name, e);
+        }
+        ChkResult {
+            result: Ok(check_run.result.is_err()),
+            ..check_run
+        }
+    }
+
+    // Negation flips a pass and a fail. An error stays an error, and a skipped check
+    // (ex: a group with only skipped children) stays skipped.
+    fn apply_negation(&self, check_run: ChkResult) -> ChkResult {
+        if !self.negated || check_run.is_check_skipped() {
+            return check_run;
+        }
+        debug!("Negating result");
+        ChkResult {
+            result: check_run.result.map(|r| !r),
+            ..check_run
+        }
     }
 
     fn exec(&self, run_env: &RunEnv) -> ChkResult {
+        // groups only wait on their children, so they don't take a job slot
+        let _slot = run_env
+            .job_slots
+            .as_ref()
+            .filter(|_| !self.fn_def.is_group)
+            .map(|slots| slots.acquire());
         let chk_scope = run_env.new_check_scope(self.instance_id);
         let check_run = (self.fn_def.check_fn)(run_env, &self.actual_params, self);
-        if self.negated {
-            debug!("Negating result");
-            let final_val = !check_run.result.unwrap();
-            ChkResult {
-                result: Ok(final_val),
-                process_out: check_run.process_out,
-                children_results: check_run.children_results,
-            }
-        } else {
-            chk_scope.emit_result(&check_run);
-            check_run
-        }
+        let check_run = self.apply_matcher(check_run);
+        let check_run = self.apply_expect_error(check_run);
+        let check_run = self.apply_negation(check_run);
+        chk_scope.emit_result(&check_run);
+        check_run
     }
 }
 
@@ -256,6 +604,9 @@ pub struct ChkInstanceBuilder<'a> {
     is_retrying: bool,
     instance_id: usize,
     is_query: bool,
+    matcher: Option<ChkMatcher>,
+    loop_vars: ChkLoopVars,
+    guard: Option<Box<ChkInstance<'a>>>,
 }
 
 impl<'a> ChkInstanceBuilder<'a> {
@@ -269,6 +620,9 @@ impl<'a> ChkInstanceBuilder<'a> {
             is_retrying: false,
             instance_id: 1000,
             is_query: false,
+            matcher: None,
+            loop_vars: vec![],
+            guard: None,
         }
     }
 
@@ -348,6 +702,22 @@ impl<'a> ChkInstanceBuilder<'a> {
         self
     }
 
+    pub fn matcher(mut self, matcher: ChkMatcher) -> Self {
+        self.is_query = true;
+        self.matcher = Some(matcher);
+        self
+    }
+
+    pub fn loop_var(mut self, name: impl Into<String>, value: ChkParamInternalValue) -> Self {
+        self.loop_vars.push((name.into(), value));
+        self
+    }
+
+    pub fn guard(mut self, guard: ChkInstance<'a>) -> Self {
+        self.guard = Some(Box::new(guard));
+        self
+    }
+
     pub fn build(self) -> ChkInstance<'a> {
         ChkInstance {
             title: self.title,
@@ -360,6 +730,9 @@ impl<'a> ChkInstanceBuilder<'a> {
             instance_id: self.instance_id,
             content_address: ContentAddress::default(), // TODO: add position info to the builder
             is_query: self.is_query,
+            matcher: self.matcher,
+            loop_vars: self.loop_vars,
+            guard: self.guard,
         }
     }
 }
@@ -368,11 +741,338 @@ pub type ChkInstId = usize;
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::predikit::data::matchers::ChkMatcherOp;
+    use crate::predikit::data::params::ChkParamInternalValue;
+    use crate::predikit::functions::builtin::{cd_all, cd_false, cd_none, cd_true};
+    use crate::predikit::functions::builtin_fs::cd_shell;
+
+    fn run_query(cmd: &str, op: ChkMatcherOp, value: ChkParamInternalValue) -> ChkResult {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        let shell = cd_shell();
+        ChkInstanceBuilder::new(&shell)
+            .param_string("cmd", cmd)
+            .matcher(ChkMatcher::new(op, value, 0..0))
+            .build()
+            .run_check_maybe_retry(&run_env)
+    }
+
+    // a query that exits non-zero doesn't produce a value to match, so it errors
+    fn erroring_check(shell: &ChkDef) -> ChkInstanceBuilder<'_> {
+        ChkInstanceBuilder::new(shell)
+            .param_string("cmd", "false")
+            .matcher(ChkMatcher::new(
+                ChkMatcherOp::Eq,
+                ChkParamInternalValue::PkString("x".to_owned()),
+                0..0,
+            ))
+    }
+
     #[test]
     fn test_chk_param_instance_force_to_string() {}
+
+    #[test]
+    fn test_negation_and_expect_error() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        let shell = cd_shell();
+        let t = cd_true();
+
+        let r = ChkInstanceBuilder::new(&t)
+            .negated(true)
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_fail());
+
+        // an error stays an error when negated
+        let r = erroring_check(&shell)
+            .negated(true)
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_error());
+
+        let r = erroring_check(&shell)
+            .param_bool(EXPECT_ERROR, true)
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_pass());
+
+        let r = ChkInstanceBuilder::new(&t)
+            .param_bool(EXPECT_ERROR, true)
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_fail());
+
+        let r = ChkInstanceBuilder::new(&t)
+            .param_bool(EXPECT_ERROR, true)
+            .negated(true)
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_pass());
+
+        // negated checks emit a result like any other check
+        drop(run_env);
+        let results: Vec<_> = rx
+            .iter()
+            .filter_map(|e| match e {
+                ChkLifecycleEvent::CheckPass(_) => Some("pass"),
+                ChkLifecycleEvent::CheckFail(_) => Some("fail"),
+                ChkLifecycleEvent::CheckError(..) => Some("error"),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec!["fail", "error", "pass", "fail", "pass"], results);
+    }
+
+    #[test]
+    fn test_query_matchers() {
+        let r = run_query(
+            "echo 5.9",
+            ChkMatcherOp::Eq,
+            ChkParamInternalValue::PkString("5.9".to_owned()),
+        );
+        assert!(r.is_check_pass());
+
+        let r = run_query(
+            "echo 42",
+            ChkMatcherOp::Lt,
+            ChkParamInternalValue::PkInt(30),
+        );
+        assert!(r.is_check_fail());
+
+        // a query that exits non-zero doesn't produce a value
+        let r = run_query(
+            "echo 5.9; false",
+            ChkMatcherOp::Eq,
+            ChkParamInternalValue::PkString("5.9".to_owned()),
+        );
+        assert!(r.is_check_error());
+    }
+
+    #[test]
+    fn test_hooks() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        let t = cd_true();
+
+        // hook output goes to events, including stderr
+        let r = ChkInstanceBuilder::new(&t)
+            .param_string(HOOK_ON_PASS, "echo hi; echo oops >&2")
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_pass());
+
+        // a failing on_init hook makes the check an error, and the result hooks still run
+        let r = ChkInstanceBuilder::new(&t)
+            .param_string(HOOK_ON_INIT, "exit 3")
+            .param_string(HOOK_ON_ERROR, "true")
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert_eq!(Err("on_init hook exited with code 3".to_owned()), r.result);
+
+        drop(run_env);
+        let hooks: Vec<ChkHookRun> = rx
+            .iter()
+            .filter_map(|e| match e {
+                ChkLifecycleEvent::HookFinish(_, hook) => Some(hook),
+                _ => None,
+            })
+            .collect();
+        let names: Vec<&str> = hooks.iter().map(|h| h.hook_name.as_str()).collect();
+        assert_eq!(vec![HOOK_ON_PASS, HOOK_ON_INIT, HOOK_ON_ERROR], names);
+
+        assert_eq!(Some("hi\n".to_owned()), hooks[0].process_out.stdout);
+        assert_eq!(Some("oops\n".to_owned()), hooks[0].process_out.stderr);
+        assert_eq!(None, hooks[0].error);
+        assert_eq!(Some(3), hooks[1].process_out.exit_code);
+        assert_eq!(Some("exited with code 3".to_owned()), hooks[1].error);
+    }
+
+    #[test]
+    fn test_hook_templates() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        let shell = cd_shell();
+
+        // hooks see the params, the result and output of the check as template
+        // values and PREDIKIT_* env vars
+        let r = ChkInstanceBuilder::new(&shell)
+            .param_string("cmd", "echo out; exit 2")
+            .param_string(HOOK_ON_INIT, "echo init {{attempt}} [{{result}}]")
+            .param_string(
+                HOOK_ON_FAIL,
+                "echo {{check}} {{result}} {{attempt}} {{exit_code}} {{stdout}}",
+            )
+            .param_string(
+                HOOK_ON_TERM,
+                "echo \"$PREDIKIT_RESULT $PREDIKIT_EXIT_CODE $PREDIKIT_STDOUT$PREDIKIT_PARAM_CMD\"",
+            )
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_fail());
+
+        // a hook that doesn't render is an error
+        let r = ChkInstanceBuilder::new(&shell)
+            .param_string("cmd", "true")
+            .param_string(HOOK_ON_INIT, "echo {{#if}}")
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_error());
+
+        drop(run_env);
+        let hooks: Vec<ChkHookRun> = rx
+            .iter()
+            .filter_map(|e| match e {
+                ChkLifecycleEvent::HookFinish(_, hook) => Some(hook),
+                _ => None,
+            })
+            .collect();
+        let stdout: Vec<&str> = hooks
+            .iter()
+            .map(|h| h.process_out.stdout.as_deref().unwrap_or_default())
+            .collect();
+        assert_eq!(
+            vec![
+                "init 1 []\n",
+                "shell fail 1 2 out\n\n",
+                "fail 2 out\necho out; exit 2\n",
+                "",
+            ],
+            stdout
+        );
+        // values are quoted for the shell
+        assert_eq!("echo 'shell' 'fail' '1' '2' 'out\n'", hooks[1].cmd);
+        assert!(hooks[3]
+            .error
+            .as_ref()
+            .is_some_and(|e| e.starts_with("Error rendering template")));
+    }
+
+    #[test]
+    fn test_hook_templates_quoted() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        let shell = cd_shell();
+
+        // output that looks like shell syntax is passed to the hook as a single word
+        let r = ChkInstanceBuilder::new(&shell)
+            .param_string(
+                "cmd",
+                r#"printf "%s" "<broken> & \"done\" 'ok'" >&2; false"#,
+            )
+            .param_string(HOOK_ON_FAIL, "echo hook-saw: {{stderr}}")
+            .param_string(HOOK_ON_TERM, "echo raw {{{exit_code}}}")
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_fail());
+
+        drop(run_env);
+        let hooks: Vec<ChkHookRun> = rx
+            .iter()
+            .filter_map(|e| match e {
+                ChkLifecycleEvent::HookFinish(_, hook) => Some(hook),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(2, hooks.len());
+        assert_eq!(None, hooks[0].error);
+        assert_eq!(
+            Some("hook-saw: <broken> & \"done\" 'ok'\n"),
+            hooks[0].process_out.stdout.as_deref()
+        );
+        assert_eq!(None, hooks[1].error);
+        assert_eq!(Some("raw 1\n"), hooks[1].process_out.stdout.as_deref());
+    }
+
+    #[test]
+    fn test_when_guards() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        let shell = cd_shell();
+        let t = cd_true();
+        let f = cd_false();
+        let all = cd_all();
+        let none = cd_none();
+
+        // a when: command that exits non-zero skips the check
+        let r = ChkInstanceBuilder::new(&t)
+            .param_string(WHEN, "exit 1")
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_skipped());
+        assert!(!r.is_check_pass() && !r.is_check_fail());
+
+        // a guard check that passes runs the check
+        let r = ChkInstanceBuilder::new(&f)
+            .guard(ChkInstanceBuilder::new(&t).build())
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_fail());
+
+        // a guard check that errors makes the check an error
+        let r = ChkInstanceBuilder::new(&t)
+            .guard(erroring_check(&shell).build())
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_error());
+
+        // skipped children don't count as a pass or fail
+        let skipped_false = || {
+            ChkInstanceBuilder::new(&f)
+                .guard(ChkInstanceBuilder::new(&f).build())
+                .build()
+        };
+        let r = ChkInstanceBuilder::new(&all)
+            .add_child(ChkInstanceBuilder::new(&t).build())
+            .add_child(skipped_false())
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_pass());
+
+        let r = ChkInstanceBuilder::new(&none)
+            .add_child(skipped_false())
+            .add_child(ChkInstanceBuilder::new(&f).build())
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_pass());
+
+        // a group with only skipped children is skipped
+        let r = ChkInstanceBuilder::new(&all)
+            .add_child(skipped_false())
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_skipped());
+
+        // guard checks don't emit events
+        drop(run_env);
+        let skips = rx
+            .iter()
+            .filter(|e| matches!(e, ChkLifecycleEvent::CheckSkip(_)))
+            .count();
+        assert_eq!(5, skips);
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct ChkProcessOut {
     pub stdout: Option<String>,
     pub stderr: Option<String>,
@@ -384,12 +1084,27 @@ pub struct ChkResult {
     pub result: Result<bool, String>,
     pub process_out: Option<ChkProcessOut>,
     pub children_results: Option<Vec<ChkResult>>,
+    // the check didn't run because its `when:` guard failed.
+    // A skipped check is neither a pass nor a fail.
+    pub skipped: bool,
+    // set by wait groups, ex: wait until_pass wait_for: d(1m) interval: d(1s) { ... }
+    pub wait_stats: Option<ChkWaitStats>,
 }
 
 impl ChkResult {
+    pub fn skipped() -> Self {
+        ChkResult {
+            result: Ok(false),
+            process_out: None,
+            children_results: None,
+            skipped: true,
+            wait_stats: None,
+        }
+    }
+
     pub fn is_check_pass(&self) -> bool {
         if let Ok(r) = self.result.as_ref() {
-            *r
+            *r && !self.skipped
         } else {
             false
         }
@@ -397,12 +1112,29 @@ impl ChkResult {
 
     pub fn is_check_fail(&self) -> bool {
         if let Ok(r) = self.result {
-            !r
+            !r && !self.skipped
         } else {
             false
         }
     }
 
+    pub fn is_check_skipped(&self) -> bool {
+        self.skipped
+    }
+
+    // pass, fail, error or skipped
+    pub fn result_name(&self) -> &'static str {
+        if self.is_check_skipped() {
+            "skipped"
+        } else if self.is_check_error() {
+            "error"
+        } else if self.is_check_pass() {
+            "pass"
+        } else {
+            "fail"
+        }
+    }
+
     pub fn is_check_error(&self) -> bool {
         self.result.is_err()
     }
@@ -413,14 +1145,19 @@ pub struct RunEnv {
     // used to send check events to a formatter, which generates (console or other) output
     pub emitter: Option<std::sync::mpsc::Sender<ChkLifecycleEvent>>,
     pub global_config: ChkActualParams,
+    // the --jobs limit, shared by every group in the run
+    pub job_slots: Option<Arc<ChkJobSlots>>,
 }
 
 impl RunEnv {
+    // a RunEnv without an emitter runs checks silently, ex: when: guards
     pub fn emit(&self, event: ChkLifecycleEvent) {
-        self.emitter.as_ref().unwrap().send(event).unwrap();
+        if let Some(emitter) = &self.emitter {
+            emitter.send(event).unwrap();
+        }
     }
 
-    pub fn new_check_scope(&self, inst_id: ChkInstId) -> ChkEventScope {
+    pub fn new_check_scope(&self, inst_id: ChkInstId) -> ChkEventScope<'_> {
         ChkEventScope::new(self, inst_id)
     }
 }
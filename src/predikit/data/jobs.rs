@@ -0,0 +1,43 @@
+// Copyright (c) 2025 Dave Parfitt
+
+use std::sync::{Condvar, Mutex};
+
+/// Limits how many checks run at once across the whole run, ex: `--jobs 4`.
+/// Only checks that aren't groups take a slot, so a group waiting on it's children
+/// never holds one, and nested parallel groups can't deadlock.
+#[derive(Debug)]
+pub struct ChkJobSlots {
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// A slot taken from ChkJobSlots, given back when it's dropped
+pub struct ChkJobSlot<'a> {
+    slots: &'a ChkJobSlots,
+}
+
+impl ChkJobSlots {
+    pub fn new(jobs: usize) -> Self {
+        Self {
+            free: Mutex::new(jobs.max(1)),
+            freed: Condvar::new(),
+        }
+    }
+
+    // blocks until a slot is free
+    pub fn acquire(&self) -> ChkJobSlot<'_> {
+        let mut free = self.free.lock().unwrap();
+        while *free == 0 {
+            free = self.freed.wait(free).unwrap();
+        }
+        *free -= 1;
+        ChkJobSlot { slots: self }
+    }
+}
+
+impl Drop for ChkJobSlot<'_> {
+    fn drop(&mut self) {
+        *self.slots.free.lock().unwrap() += 1;
+        self.slots.freed.notify_one();
+    }
+}
@@ -0,0 +1,221 @@
+// Copyright (c) 2025 Dave Parfitt
+
+use super::instance::ContentAddress;
+use super::params::ChkParamInternalValue;
+use regex::Regex;
+use std::fmt;
+
+/// The comparison operator used in a query matcher, ex: `test pkg_version? { ... } >= 3`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChkMatcherOp {
+    Eq,
+    Neq,
+    RegexMatch,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+impl ChkMatcherOp {
+    // >, >=, < and <= only make sense for ints
+    pub fn is_ordering(&self) -> bool {
+        matches!(
+            self,
+            ChkMatcherOp::Gt | ChkMatcherOp::Gte | ChkMatcherOp::Lt | ChkMatcherOp::Lte
+        )
+    }
+}
+
+impl fmt::Display for ChkMatcherOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            ChkMatcherOp::Eq => "=",
+            ChkMatcherOp::Neq => "!=",
+            ChkMatcherOp::RegexMatch => "=~",
+            ChkMatcherOp::Gt => ">",
+            ChkMatcherOp::Gte => ">=",
+            ChkMatcherOp::Lt => "<",
+            ChkMatcherOp::Lte => "<=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/// Compares the value produced by a query (its trimmed stdout) against a literal.
+#[derive(Debug, Clone)]
+pub struct ChkMatcher {
+    pub op: ChkMatcherOp,
+    pub value: ChkParamInternalValue,
+    pub content_address: ContentAddress,
+}
+
+impl ChkMatcher {
+    pub fn new(
+        op: ChkMatcherOp,
+        value: ChkParamInternalValue,
+        content_address: ContentAddress,
+    ) -> Self {
+        Self {
+            op,
+            value,
+            content_address,
+        }
+    }
+
+    /// Returns an error message if this matcher can't be evaluated, for example
+    /// an invalid regex or an ordering operator used with a string.
+    /// The compiler uses this to reject a matcher before anything runs.
+    pub fn validate(&self) -> Result<(), String> {
+        match (&self.op, &self.value) {
+            (ChkMatcherOp::RegexMatch, ChkParamInternalValue::PkString(re)) => Regex::new(re)
+                .map(|_| ())
+                .map_err(|e| format!("Invalid regex: {}", e)),
+            (ChkMatcherOp::RegexMatch, _) => {
+                Err("The =~ operator requires a string or regex value".to_owned())
+            }
+            (op, ChkParamInternalValue::PkInt(_)) if op.is_ordering() => Ok(()),
+            (op, _) if op.is_ordering() => {
+                Err(format!("The {} operator requires an Int value", op))
+            }
+            (_, ChkParamInternalValue::PkString(_))
+            | (_, ChkParamInternalValue::PkInt(_))
+            | (_, ChkParamInternalValue::PkBool(_)) => Ok(()),
+            (op, _) => Err(format!(
+                "The {} operator requires a String, Int or Bool value",
+                op
+            )),
+        }
+    }
+
+    /// Match the output of a query against this matcher. Ints are compared numerically,
+    /// so the output must parse as an int.
+    pub fn matches(&self, actual: &str) -> Result<bool, String> {
+        let actual = actual.trim();
+        match &self.value {
+            ChkParamInternalValue::PkInt(expected) => {
+                let actual_int = actual
+                    .parse::<i64>()
+                    .map_err(|_| format!("Query output \"{}\" is not a valid Int", actual))?;
+                match self.op {
+                    ChkMatcherOp::Eq => Ok(actual_int == *expected),
+                    ChkMatcherOp::Neq => Ok(actual_int != *expected),
+                    ChkMatcherOp::Gt => Ok(actual_int > *expected),
+                    ChkMatcherOp::Gte => Ok(actual_int >= *expected),
+                    ChkMatcherOp::Lt => Ok(actual_int < *expected),
+                    ChkMatcherOp::Lte => Ok(actual_int <= *expected),
+                    ChkMatcherOp::RegexMatch => Err(self.validate().unwrap_err()),
+                }
+            }
+            ChkParamInternalValue::PkString(expected) => match self.op {
+                ChkMatcherOp::Eq => Ok(actual == expected),
+                ChkMatcherOp::Neq => Ok(actual != expected),
+                ChkMatcherOp::RegexMatch => match Regex::new(expected) {
+                    Ok(re) => Ok(re.is_match(actual)),
+                    Err(e) => Err(format!("Invalid regex: {}", e)),
+                },
+                _ => Err(self.validate().unwrap_err()),
+            },
+            ChkParamInternalValue::PkBool(expected) => {
+                let actual_bool = actual
+                    .parse::<bool>()
+                    .map_err(|_| format!("Query output \"{}\" is not a valid Bool", actual))?;
+                match self.op {
+                    ChkMatcherOp::Eq => Ok(actual_bool == *expected),
+                    ChkMatcherOp::Neq => Ok(actual_bool != *expected),
+                    _ => Err(self.validate().unwrap_err()),
+                }
+            }
+            _ => Err(self.validate().unwrap_err()),
+        }
+    }
+}
+
+impl fmt::Display for ChkMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            ChkParamInternalValue::PkString(s) => write!(f, "{} \"{}\"", self.op, s),
+            ChkParamInternalValue::PkInt(i) => write!(f, "{} {}", self.op, i),
+            ChkParamInternalValue::PkBool(b) => write!(f, "{} {}", self.op, b),
+            v => write!(f, "{} {}", self.op, v),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(op: ChkMatcherOp, value: ChkParamInternalValue) -> ChkMatcher {
+        ChkMatcher::new(op, value, 0..0)
+    }
+
+    #[test]
+    fn test_string_matchers() {
+        let m = matcher(
+            ChkMatcherOp::Eq,
+            ChkParamInternalValue::PkString("5.9".to_owned()),
+        );
+        assert_eq!(Ok(true), m.matches("5.9\n"));
+        assert_eq!(Ok(false), m.matches("5.8"));
+
+        let m = matcher(
+            ChkMatcherOp::Neq,
+            ChkParamInternalValue::PkString("5.9".to_owned()),
+        );
+        assert_eq!(Ok(false), m.matches("5.9"));
+        assert_eq!(Ok(true), m.matches("5.8"));
+    }
+
+    #[test]
+    fn test_regex_matcher() {
+        let m = matcher(
+            ChkMatcherOp::RegexMatch,
+            ChkParamInternalValue::PkString(r"^1\.0\.\d+$".to_owned()),
+        );
+        assert!(m.validate().is_ok());
+        assert_eq!(Ok(true), m.matches("1.0.3"));
+        assert_eq!(Ok(false), m.matches("1.1.3"));
+
+        // a regex literal can't contain a #, \x23 matches one
+        let m = matcher(
+            ChkMatcherOp::RegexMatch,
+            ChkParamInternalValue::PkString(r"^\x23 \w+$".to_owned()),
+        );
+        assert_eq!(Ok(true), m.matches("# comment"));
+
+        let bad = matcher(
+            ChkMatcherOp::RegexMatch,
+            ChkParamInternalValue::PkString("(unclosed".to_owned()),
+        );
+        assert!(bad.validate().is_err());
+        assert!(bad.matches("anything").is_err());
+    }
+
+    #[test]
+    fn test_int_matchers() {
+        let gt = matcher(ChkMatcherOp::Gt, ChkParamInternalValue::PkInt(30));
+        assert_eq!(Ok(true), gt.matches("31"));
+        assert_eq!(Ok(false), gt.matches("30"));
+        // numeric, not lexical comparison
+        assert_eq!(Ok(true), gt.matches("100"));
+
+        let lte = matcher(ChkMatcherOp::Lte, ChkParamInternalValue::PkInt(-5));
+        assert_eq!(Ok(true), lte.matches("-5"));
+        assert_eq!(Ok(false), lte.matches("0"));
+
+        assert!(gt.matches("not a number").is_err());
+    }
+
+    #[test]
+    fn test_invalid_matchers() {
+        let m = matcher(
+            ChkMatcherOp::Gt,
+            ChkParamInternalValue::PkString("1.0".to_owned()),
+        );
+        assert!(m.validate().is_err());
+
+        let m = matcher(ChkMatcherOp::RegexMatch, ChkParamInternalValue::PkInt(1));
+        assert!(m.validate().is_err());
+    }
+}
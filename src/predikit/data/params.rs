@@ -4,6 +4,7 @@ use super::instance::ContentAddress;
 use super::ParsedDuration;
 use crate::predikit::data::ChkParamType;
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::env;
 use std::{collections::HashMap, fmt};
 
@@ -14,6 +15,10 @@ pub enum NamedType {
     PtnBool,
     PtnDuration,
     PtnPath,
+    // ex: type: List(Int)
+    PtnList(Box<NamedType>),
+    // ex: type: Map(String)
+    PtnMap(Box<NamedType>),
 }
 
 impl fmt::Display for NamedType {
@@ -24,18 +29,22 @@ impl fmt::Display for NamedType {
             NamedType::PtnBool => write!(f, "Bool"),
             NamedType::PtnDuration => write!(f, "Duration"),
             NamedType::PtnPath => write!(f, "Path"),
+            NamedType::PtnList(t) => write!(f, "List({})", t),
+            NamedType::PtnMap(t) => write!(f, "Map({})", t),
         }
     }
 }
 
 impl From<&NamedType> for ChkParamType {
     fn from(ty: &NamedType) -> ChkParamType {
-        match *ty {
+        match ty {
             NamedType::PtnString => ChkParamType::PkString,
             NamedType::PtnInt => ChkParamType::PkInt,
             NamedType::PtnBool => ChkParamType::PkBool,
             NamedType::PtnDuration => ChkParamType::PkDuration,
             NamedType::PtnPath => ChkParamType::PkPath,
+            NamedType::PtnList(t) => ChkParamType::PkList(Box::new(ChkParamType::from(t.as_ref()))),
+            NamedType::PtnMap(t) => ChkParamType::PkMap(Box::new(ChkParamType::from(t.as_ref()))),
         }
     }
 }
@@ -52,6 +61,15 @@ pub enum ChkParamInternalValue {
 
     // path has it's own type because environment vars can be referenced in a path _at runtime_
     PkPath(String),
+
+    // ex: [22, 80, 443]
+    PkList(Vec<ChkParamInternalValue>),
+    // ex: { env: "prod" }, keys are kept sorted so output is stable
+    PkMap(BTreeMap<String, ChkParamInternalValue>),
+
+    // a bare identifier, ex: `pkg_name: pkg`. Only exists at compile time, the compiler
+    // replaces it with the value of the var.
+    PkVarRef(String),
 }
 
 pub type ChkActualParams = HashMap<String, ChkActualParam>;
@@ -104,6 +122,38 @@ impl ChkActualParam {
         }
     }
 
+    pub fn new_list(
+        name: String,
+        items: Vec<ChkParamInternalValue>,
+        content_address: ContentAddress,
+    ) -> Self {
+        ChkActualParam {
+            name,
+            value: ChkParamInternalValue::PkList(items),
+            content_address,
+        }
+    }
+
+    pub fn new_map(
+        name: String,
+        entries: BTreeMap<String, ChkParamInternalValue>,
+        content_address: ContentAddress,
+    ) -> Self {
+        ChkActualParam {
+            name,
+            value: ChkParamInternalValue::PkMap(entries),
+            content_address,
+        }
+    }
+
+    pub fn new_var_ref(name: String, var_name: String, content_address: ContentAddress) -> Self {
+        ChkActualParam {
+            name,
+            value: ChkParamInternalValue::PkVarRef(var_name),
+            content_address,
+        }
+    }
+
     pub fn new_named_type(
         name: String,
         type_name: NamedType,
@@ -118,35 +168,18 @@ impl ChkActualParam {
 
     // Not to be confused with NamedType
     pub fn type_name(&self) -> String {
-        match &self.value {
-            ChkParamInternalValue::PkString(_) => "String".to_owned(),
-            ChkParamInternalValue::PkInt(_) => "Int".to_owned(),
-            ChkParamInternalValue::PkBool(_) => "Bool".to_owned(),
-            ChkParamInternalValue::PkTypeName(param_type_name) => {
-                format!("Typename({:?})", param_type_name).to_owned()
-            }
-            ChkParamInternalValue::PkDuration(_) => "Duration".to_owned(),
-            ChkParamInternalValue::PkPath(_) => "Path".to_owned(),
-        }
+        self.value.type_name()
     }
 
     pub fn is_type(&self, t: &ChkParamType) -> bool {
-        match &self.value {
-            ChkParamInternalValue::PkString(_) => *t == ChkParamType::PkString,
-            ChkParamInternalValue::PkInt(_) => *t == ChkParamType::PkInt,
-            ChkParamInternalValue::PkBool(_) => *t == ChkParamType::PkBool,
-            ChkParamInternalValue::PkTypeName(_) => *t == ChkParamType::PkTypeName,
-            ChkParamInternalValue::PkDuration(_) => *t == ChkParamType::PkDuration,
-            ChkParamInternalValue::PkPath(_) => *t == ChkParamType::PkPath,
-        }
+        self.value.is_type(t)
     }
 
     pub fn is_coercible_to_path(&self) -> bool {
-        match &self.value {
-            ChkParamInternalValue::PkString(_) => true,
-            ChkParamInternalValue::PkPath(_) => true,
-            _ => false,
-        }
+        matches!(
+            &self.value,
+            ChkParamInternalValue::PkString(_) | ChkParamInternalValue::PkPath(_)
+        )
     }
 
     // NOTE: this is matching named type names!
@@ -221,29 +254,83 @@ impl ChkActualParam {
         }
     }
 
+    pub fn get_list(&self) -> &Vec<ChkParamInternalValue> {
+        if let ChkParamInternalValue::PkList(l) = &self.value {
+            l
+        } else {
+            panic!("Expected a list, got a {}", self.type_name())
+        }
+    }
+
+    pub fn get_map(&self) -> &BTreeMap<String, ChkParamInternalValue> {
+        if let ChkParamInternalValue::PkMap(m) = &self.value {
+            m
+        } else {
+            panic!("Expected a map, got a {}", self.type_name())
+        }
+    }
+
     pub fn value_as_string(&self) -> String {
-        match &self.value {
-            ChkParamInternalValue::PkString(s) => s.to_string(),
-            ChkParamInternalValue::PkInt(i) => i.to_string(),
-            ChkParamInternalValue::PkBool(b) => b.to_string(),
-            ChkParamInternalValue::PkDuration(d) => d.to_string(),
-            ChkParamInternalValue::PkPath(p) => p.to_string(),
+        self.value.value_as_string()
+    }
+}
+
+impl ChkParamInternalValue {
+    // Lists and maps are typed by their items, ex: List(Int). An empty list or map is
+    // reported as List or Map, as there isn't anything to infer the item type from.
+    pub fn type_name(&self) -> String {
+        match self {
+            ChkParamInternalValue::PkString(_) => "String".to_owned(),
+            ChkParamInternalValue::PkInt(_) => "Int".to_owned(),
+            ChkParamInternalValue::PkBool(_) => "Bool".to_owned(),
             ChkParamInternalValue::PkTypeName(param_type_name) => {
-                format!("{:?}", param_type_name).to_string()
+                format!("Typename({:?})", param_type_name).to_owned()
             }
+            ChkParamInternalValue::PkDuration(_) => "Duration".to_owned(),
+            ChkParamInternalValue::PkPath(_) => "Path".to_owned(),
+            ChkParamInternalValue::PkList(items) => match items.first() {
+                Some(item) => format!("List({})", item.type_name()),
+                None => "List".to_owned(),
+            },
+            ChkParamInternalValue::PkMap(entries) => match entries.values().next() {
+                Some(item) => format!("Map({})", item.type_name()),
+                None => "Map".to_owned(),
+            },
+            ChkParamInternalValue::PkVarRef(_) => "VarRef".to_owned(),
         }
     }
-}
 
-impl ChkParamInternalValue {
-    pub fn is_type(&self, t: ChkParamType) -> bool {
+    pub fn is_type(&self, t: &ChkParamType) -> bool {
         match self {
-            ChkParamInternalValue::PkString(_) => t == ChkParamType::PkString,
-            ChkParamInternalValue::PkInt(_) => t == ChkParamType::PkInt,
-            ChkParamInternalValue::PkBool(_) => t == ChkParamType::PkBool,
-            ChkParamInternalValue::PkTypeName(_) => t == ChkParamType::PkTypeName,
-            ChkParamInternalValue::PkDuration(_) => t == ChkParamType::PkDuration,
-            ChkParamInternalValue::PkPath(_) => t == ChkParamType::PkPath,
+            ChkParamInternalValue::PkString(_) => *t == ChkParamType::PkString,
+            ChkParamInternalValue::PkInt(_) => *t == ChkParamType::PkInt,
+            ChkParamInternalValue::PkBool(_) => *t == ChkParamType::PkBool,
+            ChkParamInternalValue::PkTypeName(_) => *t == ChkParamType::PkTypeName,
+            ChkParamInternalValue::PkDuration(_) => *t == ChkParamType::PkDuration,
+            ChkParamInternalValue::PkPath(_) => *t == ChkParamType::PkPath,
+            ChkParamInternalValue::PkList(items) => match t {
+                ChkParamType::PkList(item_type) => items.iter().all(|i| i.is_item_type(item_type)),
+                _ => false,
+            },
+            ChkParamInternalValue::PkMap(entries) => match t {
+                ChkParamType::PkMap(item_type) => {
+                    entries.values().all(|i| i.is_item_type(item_type))
+                }
+                _ => false,
+            },
+            ChkParamInternalValue::PkVarRef(_) => false,
+        }
+    }
+
+    // items in a List(Path) or Map(Path) can be strings, the same as a Path param
+    fn is_item_type(&self, t: &ChkParamType) -> bool {
+        if *t == ChkParamType::PkPath {
+            matches!(
+                self,
+                ChkParamInternalValue::PkString(_) | ChkParamInternalValue::PkPath(_)
+            )
+        } else {
+            self.is_type(t)
         }
     }
 
@@ -286,6 +373,55 @@ impl ChkParamInternalValue {
             panic!("Expected a path");
         }
     }
+
+    pub fn value_as_string(&self) -> String {
+        match self {
+            ChkParamInternalValue::PkString(s) => s.to_string(),
+            ChkParamInternalValue::PkInt(i) => i.to_string(),
+            ChkParamInternalValue::PkBool(b) => b.to_string(),
+            ChkParamInternalValue::PkDuration(d) => d.to_string(),
+            ChkParamInternalValue::PkPath(p) => p.to_string(),
+            ChkParamInternalValue::PkTypeName(param_type_name) => {
+                format!("{:?}", param_type_name).to_string()
+            }
+            ChkParamInternalValue::PkList(items) => format!(
+                "[{}]",
+                items
+                    .iter()
+                    .map(|i| i.value_as_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ChkParamInternalValue::PkMap(entries) => format!(
+                "{{ {} }}",
+                entries
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, v.value_as_string()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ChkParamInternalValue::PkVarRef(name) => name.to_string(),
+        }
+    }
+
+    // Used as the data when rendering a tool's handlebars templates, lists and maps
+    // become arrays and objects so they can be used with {{#each}}
+    pub fn to_template_value(&self) -> serde_json::Value {
+        match self {
+            ChkParamInternalValue::PkInt(i) => serde_json::Value::from(*i),
+            ChkParamInternalValue::PkBool(b) => serde_json::Value::from(*b),
+            ChkParamInternalValue::PkList(items) => {
+                serde_json::Value::Array(items.iter().map(|i| i.to_template_value()).collect())
+            }
+            ChkParamInternalValue::PkMap(entries) => serde_json::Value::Object(
+                entries
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.to_template_value()))
+                    .collect(),
+            ),
+            v => serde_json::Value::from(v.value_as_string()),
+        }
+    }
 }
 
 impl fmt::Display for ChkParamInternalValue {
@@ -304,6 +440,44 @@ fn shellexpand_context(s: &str) -> Result<Option<Cow<'static, str>>, env::VarErr
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn foo() {}
+
+    #[test]
+    fn test_list_and_map_types() {
+        let ports = ChkParamInternalValue::PkList(vec![
+            ChkParamInternalValue::PkInt(22),
+            ChkParamInternalValue::PkInt(80),
+        ]);
+        assert!(ports.is_type(&ChkParamType::PkList(Box::new(ChkParamType::PkInt))));
+        assert!(!ports.is_type(&ChkParamType::PkList(Box::new(ChkParamType::PkString))));
+        assert!(!ports.is_type(&ChkParamType::PkInt));
+        assert_eq!("List(Int)", ports.type_name());
+        assert_eq!("[22, 80]", ports.value_as_string());
+
+        // strings are accepted as paths
+        let paths = ChkParamInternalValue::PkList(vec![
+            ChkParamInternalValue::PkString("/tmp".to_owned()),
+            ChkParamInternalValue::PkPath("$HOME".to_owned()),
+        ]);
+        assert!(paths.is_type(&ChkParamType::PkList(Box::new(ChkParamType::PkPath))));
+
+        let empty = ChkParamInternalValue::PkList(vec![]);
+        assert!(empty.is_type(&ChkParamType::PkList(Box::new(ChkParamType::PkBool))));
+
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            "env".to_owned(),
+            ChkParamInternalValue::PkString("prod".to_owned()),
+        );
+        let labels = ChkParamInternalValue::PkMap(entries);
+        assert!(labels.is_type(&ChkParamType::PkMap(Box::new(ChkParamType::PkString))));
+        assert_eq!("Map(String)", labels.type_name());
+        assert_eq!(
+            serde_json::json!({"env": "prod"}),
+            labels.to_template_value()
+        );
+    }
 }
@@ -0,0 +1,242 @@
+// Copyright (c) 2025 Dave Parfitt
+
+use log::debug;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::instance::{ChkProcessOut, ChkResult};
+
+pub const TIMEOUT: &str = "timeout";
+
+// how long a timed out process group has to exit after SIGTERM before it gets a SIGKILL
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The output of a `sh -c` command. If the command timed out, stdout and stderr
+/// contain whatever the process wrote before it was killed.
+#[derive(Debug)]
+pub struct ShellOutput {
+    pub process_out: ChkProcessOut,
+    pub success: bool,
+    pub timed_out: Option<Duration>,
+}
+
+type OutputBuf = Arc<Mutex<Vec<u8>>>;
+
+// read a pipe on it's own thread so a process that fills one pipe while we're
+// reading the other can't block, and so we still have the output if it's killed
+fn spawn_reader<R: Read + Send + 'static>(pipe: Option<R>, buf: OutputBuf) -> JoinHandle<()> {
+    thread::spawn(move || {
+        if let Some(mut pipe) = pipe {
+            let mut chunk = [0u8; 4096];
+            while let Ok(n) = pipe.read(&mut chunk) {
+                if n == 0 {
+                    break;
+                }
+                buf.lock().unwrap().extend_from_slice(&chunk[..n]);
+            }
+        }
+    })
+}
+
+fn signal_group(pgid: u32, signal: libc::c_int) -> bool {
+    // the child is the leader of it's own process group, so a negative pid
+    // signals everything it started too
+    // SAFETY: kill() doesn't touch any memory, and the worst case is ESRCH if the
+    // group has already exited
+    unsafe { libc::kill(-(pgid as libc::pid_t), signal) == 0 }
+}
+
+fn wait_until(child: &mut Child, deadline: Option<Instant>) -> std::io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// SIGTERM the process group, and SIGKILL it if anything in it is still running after
+// the grace period. The leader exiting isn't enough, the rest of the group may have
+// ignored SIGTERM and still be holding the pipes open.
+fn kill_group(child: &mut Child) -> std::io::Result<ExitStatus> {
+    let pgid = child.id();
+    signal_group(pgid, libc::SIGTERM);
+    let deadline = Instant::now() + KILL_GRACE_PERIOD;
+    let mut status = None;
+    loop {
+        if status.is_none() {
+            status = child.try_wait()?;
+        }
+        // signal 0 only checks if the group still has any processes in it
+        if let Some(status) = status.filter(|_| !signal_group(pgid, 0)) {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    debug!("Process group {} ignored SIGTERM, sending SIGKILL", pgid);
+    signal_group(pgid, libc::SIGKILL);
+    match status {
+        Some(status) => Ok(status),
+        None => child.wait(),
+    }
+}
+
+// A process that moved to another process group can keep the pipes open after the
+// group is killed, so don't wait on the readers forever once the command timed out.
+fn join_readers(readers: [JoinHandle<()>; 2], timed_out: bool) {
+    if timed_out {
+        let deadline = Instant::now() + KILL_GRACE_PERIOD;
+        while !readers.iter().all(|r| r.is_finished()) && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+    for r in readers {
+        if !timed_out || r.is_finished() {
+            let _ = r.join();
+        } else {
+            debug!("Output pipe is still open after the process group was killed");
+        }
+    }
+}
+
+/// Run a command with `sh -c`. With a timeout, the command's process group is
+/// killed if it runs for longer than the timeout.
+pub fn run_shell(cmd: &str, timeout: Option<Duration>) -> std::io::Result<ShellOutput> {
+    run_shell_with_env(cmd, timeout, &[])
+}
+
+/// Like run_shell, with extra environment variables for the command, ex: PREDIKIT_* for hooks
+pub fn run_shell_with_env(
+    cmd: &str,
+    timeout: Option<Duration>,
+    env: &[(String, String)],
+) -> std::io::Result<ShellOutput> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .envs(env.iter().cloned())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+
+    let stdout = OutputBuf::default();
+    let stderr = OutputBuf::default();
+    let readers = [
+        spawn_reader(child.stdout.take(), stdout.clone()),
+        spawn_reader(child.stderr.take(), stderr.clone()),
+    ];
+
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let (status, timed_out) = match wait_until(&mut child, deadline)? {
+        Some(status) => (status, None),
+        None => {
+            debug!("Command [{}] timed out, killing process group", cmd);
+            (kill_group(&mut child)?, timeout)
+        }
+    };
+
+    join_readers(readers, timed_out.is_some());
+
+    let to_string = |buf: OutputBuf| String::from_utf8_lossy(&buf.lock().unwrap()).to_string();
+    Ok(ShellOutput {
+        process_out: ChkProcessOut {
+            stdout: Some(to_string(stdout)),
+            stderr: Some(to_string(stderr)),
+            exit_code: status.code(),
+        },
+        success: status.success() && timed_out.is_none(),
+        timed_out,
+    })
+}
+
+/// Run a command with `sh -c` and turn the exit status into a check result. A
+/// command that times out is an error.
+pub fn run_shell_check(cmd: &str, timeout: Option<Duration>) -> ChkResult {
+    let (result, process_out) = match run_shell(cmd, timeout) {
+        Ok(ShellOutput {
+            process_out,
+            timed_out: Some(t),
+            ..
+        }) => (
+            Err(format!("Timed out after {}", humantime::format_duration(t))),
+            Some(process_out),
+        ),
+        Ok(out) => {
+            debug!("SHELL RESULT = {:?}", out.process_out.exit_code);
+            (Ok(out.success), Some(out.process_out))
+        }
+        Err(e) => (Err(e.to_string()), None),
+    };
+    ChkResult {
+        result,
+        process_out,
+        children_results: None,
+        skipped: false,
+        wait_stats: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_shell() {
+        let r = run_shell_check("echo foo; echo bar >&2; exit 3", None);
+        assert_eq!(Ok(false), r.result);
+        let out = r.process_out.unwrap();
+        assert_eq!(Some("foo\n".to_owned()), out.stdout);
+        assert_eq!(Some("bar\n".to_owned()), out.stderr);
+        assert_eq!(Some(3), out.exit_code);
+
+        let r = run_shell_check("true", Some(Duration::from_secs(5)));
+        assert!(r.is_check_pass());
+    }
+
+    #[test]
+    fn test_run_shell_timeout() {
+        let start = Instant::now();
+        let r = run_shell_check("echo partial; sleep 10", Some(Duration::from_millis(200)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(Err("Timed out after 200ms".to_owned()), r.result);
+        let out = r.process_out.unwrap();
+        assert_eq!(Some("partial\n".to_owned()), out.stdout);
+        assert_eq!(None, out.exit_code);
+
+        // the whole process group is killed, even if sh ignores SIGTERM
+        let start = Instant::now();
+        let r = run_shell_check(
+            "trap '' TERM; sleep 10 & wait",
+            Some(Duration::from_millis(100)),
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(r.is_check_error());
+    }
+
+    #[test]
+    fn test_run_shell_timeout_term_ignored_in_group() {
+        // sh exits on SIGTERM, but the subshell and it's sleep ignore it and keep
+        // the pipes open, so the group has to get a SIGKILL after the grace period
+        let start = Instant::now();
+        let r = run_shell_check(
+            "(trap '' TERM; sleep 20); echo done",
+            Some(Duration::from_millis(300)),
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(Err("Timed out after 300ms".to_owned()), r.result);
+        assert_eq!(Some(String::new()), r.process_out.unwrap().stdout);
+    }
+}
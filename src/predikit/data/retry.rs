@@ -0,0 +1,137 @@
+// Copyright (c) 2025 Dave Parfitt
+
+use std::time::{Duration, Instant};
+
+use super::params::ChkActualParams;
+
+pub const RETRIES: &str = "retries";
+pub const RETRY_DELAY: &str = "retry_delay";
+pub const RETRY_BACKOFF: &str = "retry_backoff";
+pub const RETRY_MAX_DELAY: &str = "retry_max_delay";
+pub const RETRY_JITTER: &str = "retry_jitter";
+pub const RETRY_FOR: &str = "retry_for";
+
+/// How a retrying check (ex: `@test`) is retried, built from the retry_* params of a check instance.
+#[derive(Debug, Clone)]
+pub struct ChkRetryPolicy {
+    // the max number of attempts, there's no limit if only retry_for is set
+    pub retries: Option<u64>,
+    pub delay: Duration,
+    // the delay is multiplied by backoff after each attempt, 1 keeps the delay fixed
+    pub backoff: u32,
+    // caps the delay after backoff is applied, but not the jitter
+    pub max_delay: Option<Duration>,
+    // a random amount of time up to jitter is added to each sleep
+    pub jitter: Option<Duration>,
+    // stop retrying once this much time has passed since the first attempt
+    pub retry_for: Option<Duration>,
+}
+
+impl ChkRetryPolicy {
+    // the compiler checks the param types, and that retries or retry_for is set
+    pub fn from_params(params: &ChkActualParams) -> Self {
+        let duration = |name: &str| params.get(name).map(|p| p.get_duration().duration);
+        Self {
+            retries: params.get(RETRIES).map(|p| p.get_int() as u64),
+            delay: duration(RETRY_DELAY).unwrap_or_default(),
+            backoff: params.get(RETRY_BACKOFF).map_or(1, |p| p.get_int() as u32),
+            max_delay: duration(RETRY_MAX_DELAY),
+            jitter: duration(RETRY_JITTER),
+            retry_for: duration(RETRY_FOR),
+        }
+    }
+
+    // the delay after an attempt fails, without jitter
+    pub fn delay_after_attempt(&self, attempt_num: u64) -> Duration {
+        let exp = attempt_num.saturating_sub(1).min(u32::MAX as u64) as u32;
+        let delay = self.delay.saturating_mul(self.backoff.saturating_pow(exp));
+        match self.max_delay {
+            Some(max_delay) => delay.min(max_delay),
+            None => delay,
+        }
+    }
+
+    /// How long to sleep before the next attempt, or None if the check is out of
+    /// attempts or out of time. `started` is the start of the first attempt.
+    pub fn next_sleep(&self, attempt_num: u64, started: Instant) -> Option<Duration> {
+        if self.retries.is_some_and(|retries| attempt_num >= retries) {
+            return None;
+        }
+
+        let mut sleep = self.delay_after_attempt(attempt_num);
+        if let Some(jitter) = self.jitter {
+            sleep += jitter.mul_f64(fastrand::f64());
+        }
+
+        if let Some(retry_for) = self.retry_for {
+            let remaining = retry_for.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                return None;
+            }
+            // the last attempt runs at the deadline
+            sleep = sleep.min(remaining);
+        }
+        Some(sleep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(retries: Option<u64>, delay_ms: u64) -> ChkRetryPolicy {
+        ChkRetryPolicy {
+            retries,
+            delay: Duration::from_millis(delay_ms),
+            backoff: 1,
+            max_delay: None,
+            jitter: None,
+            retry_for: None,
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut p = policy(Some(10), 100);
+        assert_eq!(Duration::from_millis(100), p.delay_after_attempt(1));
+        assert_eq!(Duration::from_millis(100), p.delay_after_attempt(5));
+
+        p.backoff = 2;
+        assert_eq!(Duration::from_millis(100), p.delay_after_attempt(1));
+        assert_eq!(Duration::from_millis(200), p.delay_after_attempt(2));
+        assert_eq!(Duration::from_millis(800), p.delay_after_attempt(4));
+
+        p.max_delay = Some(Duration::from_millis(500));
+        assert_eq!(Duration::from_millis(500), p.delay_after_attempt(4));
+        // doesn't overflow
+        assert_eq!(Duration::from_millis(500), p.delay_after_attempt(1000));
+    }
+
+    #[test]
+    fn test_next_sleep() {
+        let started = Instant::now();
+        let p = policy(Some(3), 100);
+        assert_eq!(Some(Duration::from_millis(100)), p.next_sleep(1, started));
+        assert_eq!(Some(Duration::from_millis(100)), p.next_sleep(2, started));
+        assert_eq!(None, p.next_sleep(3, started));
+
+        let mut p = policy(Some(3), 100);
+        p.jitter = Some(Duration::from_millis(50));
+        let sleep = p.next_sleep(1, started).unwrap();
+        assert!(sleep >= Duration::from_millis(100) && sleep <= Duration::from_millis(150));
+
+        // retry_for without retries keeps going until the deadline, and doesn't
+        // sleep past it
+        let mut p = policy(None, 100);
+        p.retry_for = Some(Duration::from_secs(60));
+        assert_eq!(
+            Some(Duration::from_millis(100)),
+            p.next_sleep(1000, started)
+        );
+        p.retry_for = Some(Duration::from_millis(10));
+        let remaining = p.next_sleep(1, started);
+        assert!(remaining.is_none() || remaining.unwrap() <= Duration::from_millis(10));
+        p.retry_for = Some(Duration::ZERO);
+        assert_eq!(None, p.next_sleep(1, started));
+    }
+}
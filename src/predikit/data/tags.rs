@@ -0,0 +1,123 @@
+// Copyright (c) 2025 Dave Parfitt
+
+use super::instance::ChkInstance;
+
+/// Selects which checks run via `--tags` and `--exclude-tags`. Tags are inherited, so
+/// a check has it's own tags plus the tags of all of it's parent groups.
+#[derive(Debug, Default)]
+pub struct TagFilter {
+    // if not empty, only checks with at least one of these tags are run
+    pub include: Vec<String>,
+    // checks with any of these tags are never run
+    pub exclude: Vec<String>,
+}
+
+impl TagFilter {
+    pub fn new(include: Vec<String>, exclude: Vec<String>) -> Self {
+        Self { include, exclude }
+    }
+
+    /// Remove the checks that aren't selected from a compiled instance tree.
+    /// Groups that are left without any children are removed as well.
+    pub fn prune<'a>(&self, insts: Vec<ChkInstance<'a>>) -> Vec<ChkInstance<'a>> {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return insts;
+        }
+        insts
+            .into_iter()
+            .filter_map(|inst| self.prune_instance(inst, &[]))
+            .collect()
+    }
+
+    fn prune_instance<'a>(
+        &self,
+        mut inst: ChkInstance<'a>,
+        inherited_tags: &[String],
+    ) -> Option<ChkInstance<'a>> {
+        let mut tags = inherited_tags.to_vec();
+        tags.extend(inst.tags());
+
+        if tags.iter().any(|t| self.exclude.contains(t)) {
+            return None;
+        }
+
+        if inst.children.is_empty() {
+            let selected = self.include.is_empty() || tags.iter().any(|t| self.include.contains(t));
+            return selected.then_some(inst);
+        }
+
+        inst.children = std::mem::take(&mut inst.children)
+            .into_iter()
+            .filter_map(|child| self.prune_instance(child, &tags))
+            .collect();
+        (!inst.children.is_empty()).then_some(inst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predikit::data::instance::ChkInstanceBuilder;
+    use crate::predikit::data::params::{ChkActualParam, ChkParamInternalValue};
+    use crate::predikit::data::ChkDef;
+    use crate::predikit::functions::builtin::{cd_all, cd_true};
+
+    fn tagged<'a>(def: &'a ChkDef, tags: &[&str]) -> ChkInstanceBuilder<'a> {
+        let items = tags
+            .iter()
+            .map(|t| ChkParamInternalValue::PkString(t.to_string()))
+            .collect();
+        ChkInstanceBuilder::new(def).param(
+            "tags",
+            ChkActualParam::new_list("tags".to_owned(), items, 0..0),
+        )
+    }
+
+    // all [net] { true! [slow], true! }, true! [local]
+    fn make_tree<'a>(all: &'a ChkDef, t: &'a ChkDef) -> Vec<ChkInstance<'a>> {
+        vec![
+            tagged(all, &["net"])
+                .add_child(tagged(t, &["slow"]).instance_id(1).build())
+                .add_child(ChkInstanceBuilder::new(t).instance_id(2).build())
+                .instance_id(0)
+                .build(),
+            tagged(t, &["local"]).instance_id(3).build(),
+        ]
+    }
+
+    fn ids(insts: &[ChkInstance]) -> Vec<usize> {
+        let mut v = vec![];
+        for i in insts {
+            v.push(i.instance_id);
+            v.extend(ids(&i.children));
+        }
+        v
+    }
+
+    #[test]
+    fn test_tag_filter() {
+        let all = cd_all();
+        let t = cd_true();
+
+        let f = TagFilter::default();
+        assert_eq!(vec![0, 1, 2, 3], ids(&f.prune(make_tree(&all, &t))));
+
+        // children inherit the tags of their group
+        let f = TagFilter::new(vec!["net".to_owned()], vec![]);
+        assert_eq!(vec![0, 1, 2], ids(&f.prune(make_tree(&all, &t))));
+
+        let f = TagFilter::new(vec!["slow".to_owned(), "local".to_owned()], vec![]);
+        assert_eq!(vec![0, 1, 3], ids(&f.prune(make_tree(&all, &t))));
+
+        let f = TagFilter::new(vec![], vec!["slow".to_owned()]);
+        assert_eq!(vec![0, 2, 3], ids(&f.prune(make_tree(&all, &t))));
+
+        // exclude wins over include
+        let f = TagFilter::new(vec!["net".to_owned()], vec!["slow".to_owned()]);
+        assert_eq!(vec![0, 2], ids(&f.prune(make_tree(&all, &t))));
+
+        // groups without any selected children are dropped
+        let f = TagFilter::new(vec!["local".to_owned()], vec![]);
+        assert_eq!(vec![3], ids(&f.prune(make_tree(&all, &t))));
+    }
+}
@@ -13,4 +13,5 @@ pub struct ToolDef {
     pub instance_params: Vec<ChkFormalParam>,
     pub shell: Option<String>, // internally will default to "sh -c", maybe you want to us ksh?
     pub accepts_retry: bool,
+    pub is_query: bool, // stdout is the produced value, see ChkInstance.matcher
 }
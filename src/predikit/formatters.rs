@@ -1,9 +1,19 @@
 // Copyright (c) 2025 Dave Parfitt
 
-use crate::predikit::data::events::ChkLifecycleEvent;
+use std::collections::BTreeMap;
+use std::io::{stdout, Write};
+
+use crate::predikit::data::events::{ChkDesc, ChkDescMap, ChkLifecycleEvent};
+use crate::predikit::data::instance::TITLE;
 
 pub mod default;
+pub mod json;
+pub mod junit;
+pub mod tap;
 
+/// Formatters get the events of each file between an Init and a Term. A check gets a
+/// CheckStart for every attempt when it's retrying, or when it's in a retrying group,
+/// and its result is the result of the last attempt.
 pub trait OutputFormatter {
     fn init(&mut self, cfg: FormatterConfig);
     fn process_events(&mut self, receiver: std::sync::mpsc::Receiver<ChkLifecycleEvent>);
@@ -12,10 +22,162 @@ pub trait OutputFormatter {
 
 pub struct FormatterConfig {
     pub color: bool,
+    // where a formatter writes it's report, stdout if there isn't one
+    pub output: Option<FormatterOutput>,
 }
 
 impl Default for FormatterConfig {
     fn default() -> Self {
-        FormatterConfig { color: true }
+        FormatterConfig {
+            color: true,
+            output: None,
+        }
+    }
+}
+
+pub type FormatterOutput = Box<dyn Write + Send>;
+
+pub fn output_or_stdout(output: &mut Option<FormatterOutput>) -> &mut FormatterOutput {
+    output.get_or_insert_with(|| Box::new(stdout()))
+}
+
+// The checks at the top level of a file
+pub fn root_checks(chk_inst_map: &ChkDescMap) -> Vec<&ChkDesc> {
+    let mut roots: Vec<&ChkDesc> = chk_inst_map.values().filter(|chk| chk.is_root).collect();
+    // root checks are numbered in the order they appear in the file
+    roots.sort_by_key(|chk| chk.instance_id);
+    roots
+}
+
+// ex: [Database is up] port_open? port: 5432
+// Params are sorted so a check is described the same way from run to run
+pub fn check_description(chk: &ChkDesc) -> String {
+    let title = chk
+        .actual_params
+        .get(TITLE)
+        .map(|t| format!("[{}] ", t.get_string()))
+        .unwrap_or_default();
+    let negate = if chk.negated { "not " } else { "" };
+    let mut params: Vec<String> = chk
+        .fn_desc
+        .formal_params
+        .keys()
+        .filter_map(|name| {
+            chk.actual_params
+                .get(name)
+                .map(|p| format!("{}: {}", name, p.value_as_string()))
+        })
+        .collect();
+    params.sort();
+    format!(
+        "{}{}{} {}",
+        title,
+        negate,
+        chk.fn_desc.fn_name,
+        params.join(", ")
+    )
+    .trim_end()
+    .to_owned()
+}
+
+pub const DEFAULT_FORMAT: &str = "default";
+
+// formatters run on the listener thread
+pub type BoxedFormatter = Box<dyn OutputFormatter + Send>;
+pub type FormatterCtor = fn() -> BoxedFormatter;
+
+/// Output formatters by the name used with `--format`
+#[derive(Default)]
+pub struct FormatterRegistry {
+    formatters: BTreeMap<String, FormatterCtor>,
+}
+
+impl FormatterRegistry {
+    pub fn new_with_builtins() -> FormatterRegistry {
+        let mut reg = FormatterRegistry::default();
+        reg.register(DEFAULT_FORMAT, || {
+            Box::new(default::DefaultOutputFormatter::default())
+        });
+        reg.register("json", || Box::new(json::JsonOutputFormatter::default()));
+        reg.register("ndjson", || {
+            Box::new(json::JsonOutputFormatter::new_ndjson())
+        });
+        reg.register("junit", || Box::new(junit::JUnitOutputFormatter::default()));
+        reg.register("tap", || Box::new(tap::TapOutputFormatter::default()));
+        reg
+    }
+
+    pub fn register(&mut self, name: &str, ctor: FormatterCtor) {
+        self.formatters.insert(name.to_owned(), ctor);
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.formatters.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// Create and init the formatter registered as `name`
+    pub fn create(&self, name: &str, cfg: FormatterConfig) -> Option<BoxedFormatter> {
+        let mut formatter = self.formatters.get(name)?();
+        formatter.init(cfg);
+        Some(formatter)
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use crate::predikit::comp::compiler::{compile_checks_to_asts, make_tools};
+    use crate::predikit::comp::pkparser;
+    use crate::predikit::data::events::{desc_from_instances, ChkLifecycleEvent};
+    use crate::predikit::data::instance::RunEnv;
+    use crate::predikit::data::ChkDefRegistry;
+    use std::io::Write;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::sync::{Arc, Mutex};
+
+    pub const TEST_FILE: &str = "test.pk";
+
+    /// Compile and run the checks in source as TEST_FILE, and return the events of the run
+    pub fn run_source_to_events(source: &str) -> Receiver<ChkLifecycleEvent> {
+        let mut fns = ChkDefRegistry::new_with_builtins();
+        let ast_file = pkparser::TopLevelParser::new().parse(source).unwrap();
+        assert!(make_tools(&mut fns, vec![ast_file.tools]).is_empty());
+        let cfos = compile_checks_to_asts(&fns, vec![ast_file.checks]);
+        let cfo = cfos.first().unwrap();
+        assert!(cfo.errors.is_empty());
+
+        let (tx, rx) = channel();
+        let run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        run_env.emit(ChkLifecycleEvent::Init(
+            desc_from_instances(&cfo.instances),
+            Some(TEST_FILE.to_owned()),
+        ));
+        for inst in &cfo.instances {
+            inst.run_check_maybe_retry(&run_env);
+        }
+        run_env.emit(ChkLifecycleEvent::Term(Some(TEST_FILE.to_owned())));
+        rx
+    }
+
+    /// A formatter output that can be read back once the formatter is done with it
+    #[derive(Clone, Default)]
+    pub struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedOutput {
+        pub fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
     }
 }
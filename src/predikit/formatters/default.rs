@@ -5,8 +5,10 @@ use crate::predikit::data::events::{ChkDesc, ChkDescMap, ChkDescResult, ChkLifec
 use crate::predikit::data::instance::ChkInstId;
 use colored::Colorize;
 use log::debug;
+use std::collections::HashSet;
 use std::io::stdout;
 use std::io::Write;
+use std::time::Duration;
 
 use super::{FormatterConfig, OutputFormatter};
 
@@ -15,10 +17,50 @@ enum PathType {
     Check,
 }
 
+// The results of the checks in a file that aren't groups, and if all of it's root checks passed
+#[derive(Default)]
+struct FileSummary {
+    filename: String,
+    pass: usize,
+    fail: usize,
+    error: usize,
+    skipped: usize,
+    roots_pass: bool,
+    some_roots_pass: bool,
+}
+
+impl FileSummary {
+    fn checks(&self) -> usize {
+        self.pass + self.fail + self.error + self.skipped
+    }
+
+    fn add(&mut self, other: &FileSummary) {
+        self.pass += other.pass;
+        self.fail += other.fail;
+        self.error += other.error;
+        self.skipped += other.skipped;
+    }
+
+    fn counts(&self) -> String {
+        format!(
+            "{} checks: {} pass, {} fail, {} error, {} skipped",
+            self.checks(),
+            self.pass,
+            self.fail,
+            self.error,
+            self.skipped
+        )
+    }
+}
+
 #[derive(Default)]
 pub struct DefaultOutputFormatter {
     config: FormatterConfig,
     chk_inst_map: ChkDescMap,
+    files: Vec<FileSummary>,
+    wall_time: Option<Duration>,
+    // wait groups, their children run without events
+    waited: HashSet<ChkInstId>,
 }
 
 fn show_tree(path_stack: &[PathType]) {
@@ -37,14 +79,16 @@ impl OutputFormatter for DefaultOutputFormatter {
         self.process_loop(receiver);
     }
 
-    fn term(&mut self) {}
+    fn term(&mut self) {
+        self.finish_processing_events();
+    }
 }
 
 impl DefaultOutputFormatter {
     pub fn new(config: FormatterConfig) -> Self {
         Self {
             config,
-            chk_inst_map: ChkDescMap::new(),
+            ..Self::default()
         }
     }
 
@@ -76,32 +120,110 @@ impl DefaultOutputFormatter {
         chk.fn_desc
             .formal_params
             .iter()
-            .map(|param| {
-                let param_name = param.0.clone();
-                let param_value = chk.actual_params.get(&param_name).unwrap();
-                format!("{}: {}", param_name, param_value.value_as_string())
+            .filter_map(|(param_name, formal_param)| {
+                // optional params may not be set, show the default value if there is one
+                if let Some(param_value) = chk.actual_params.get(param_name) {
+                    Some(format!("{}: {}", param_name, param_value.value_as_string()))
+                } else {
+                    formal_param
+                        .param_default
+                        .as_ref()
+                        .map(|d| format!("{}: {} (default)", param_name, d.value_as_string()))
+                }
             })
             .collect::<Vec<String>>()
             .join(", ")
     }
 
-    pub fn process_events(&mut self, receiver: std::sync::mpsc::Receiver<ChkLifecycleEvent>) {
-        self.process_loop(receiver);
-        self.finish_processing_events();
+    // show the for loop iteration that a check was expanded from, ex: (for pkg = docker)
+    fn fancy_loop_vars(&self, chk: &ChkDesc) -> String {
+        if chk.loop_vars.is_empty() {
+            return "".to_string();
+        }
+        let vars = chk
+            .loop_vars
+            .iter()
+            .map(|(name, value)| format!("{} = {}", name, value.value_as_string()))
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!(" (for {})", vars)
     }
 
-    fn finish_processing_events(&mut self) {
+    fn summarize_file(&self, filename: String) -> FileSummary {
         let root_checks = self.get_root_checks();
         debug!("{:#?}", root_checks);
-        let all_pass = root_checks
+        let mut summary = FileSummary {
+            filename,
+            // skipped root checks don't count as a pass or a fail
+            roots_pass: root_checks.iter().all(|chk| {
+                matches!(
+                    chk.result,
+                    Some(ChkDescResult::Pass) | Some(ChkDescResult::Skipped)
+                )
+            }),
+            some_roots_pass: root_checks
+                .iter()
+                .any(|chk| matches!(chk.result, Some(ChkDescResult::Pass))),
+            ..FileSummary::default()
+        };
+        for chk in root_checks {
+            self.count_checks(chk, &mut summary);
+        }
+        summary
+    }
+
+    // Count the checks that aren't groups. The children of a wait group never report a
+    // result, so the wait group is counted instead.
+    fn count_checks(&self, chk: &ChkDesc, summary: &mut FileSummary) {
+        if chk.is_group && !self.waited.contains(&chk.instance_id) {
+            for child in chk
+                .children
+                .iter()
+                .filter_map(|id| self.chk_inst_map.get(id))
+            {
+                self.count_checks(child, summary);
+            }
+            return;
+        }
+        match chk.result {
+            Some(ChkDescResult::Pass) => summary.pass += 1,
+            Some(ChkDescResult::Fail) => summary.fail += 1,
+            Some(ChkDescResult::Error) => summary.error += 1,
+            // a check that never reported a result didn't run
+            Some(ChkDescResult::Skipped) | None => summary.skipped += 1,
+        }
+    }
+
+    // One summary for every file in the run
+    fn finish_processing_events(&mut self) {
+        let mut total = FileSummary::default();
+        let width = self
+            .files
             .iter()
-            .all(|chk| matches!(chk.result, Some(ChkDescResult::Pass)));
+            .map(|f| f.filename.len())
+            .max()
+            .unwrap_or_default()
+            .max("Total".len());
+
+        println!("\n* Summary:");
+        for file in &self.files {
+            println!("  {:width$}  {}", file.filename, file.counts());
+            total.add(file);
+        }
+        let wall_time = self
+            .wall_time
+            .map(|d| {
+                let d = Duration::from_millis(d.as_millis() as u64);
+                format!(" in {}", humantime::format_duration(d))
+            })
+            .unwrap_or_default();
+        println!("  {:width$}  {}{}", "Total", total.counts(), wall_time);
+
+        let all_pass = self.files.iter().all(|f| f.roots_pass);
         if all_pass {
             println!("{}", "All root checks passed".truecolor(0, 200, 0));
         } else {
-            let some_pass = root_checks
-                .iter()
-                .any(|chk| matches!(chk.result, Some(ChkDescResult::Pass)));
+            let some_pass = self.files.iter().any(|f| f.some_roots_pass);
             if some_pass {
                 println!("{}", "Some root checks failed".truecolor(200, 0, 0));
             } else {
@@ -121,8 +243,14 @@ impl DefaultOutputFormatter {
 
     fn process_loop(&mut self, receiver: std::sync::mpsc::Receiver<ChkLifecycleEvent>) {
         let mut path_stack: Vec<PathType> = vec![];
+        // a wait group redraws it's countdown on the same line
+        let mut waiting = false;
 
         for event in receiver.iter() {
+            if waiting && !matches!(event, CheckWaitTick(..)) {
+                println!();
+                waiting = false;
+            }
             match event {
                 Init(checks, filename) => {
                     println!(
@@ -130,6 +258,7 @@ impl DefaultOutputFormatter {
                         filename.unwrap_or("<no file>".to_string())
                     );
                     self.chk_inst_map = checks;
+                    self.waited.clear();
                     debug!("CHECKS: {:#?}", self.chk_inst_map);
                 }
 
@@ -151,6 +280,10 @@ impl DefaultOutputFormatter {
                         chk.fn_desc.fn_name.blue()
                     );
                     print!("{}", self.fancy_params(chk));
+                    if let Some(matcher) = &chk.matcher {
+                        print!(" {}", matcher.to_string().purple());
+                    }
+                    print!("{}", self.fancy_loop_vars(chk).yellow());
                     if chk.is_group {
                         println!();
                     }
@@ -160,8 +293,14 @@ impl DefaultOutputFormatter {
                     println!(" [{}μs]", duration.as_micros());
                     let _ = path_stack.pop();
                 }
-                CheckRetrySleep(_inst_id, seconds) => {
-                    let msg = format!("  Sleep {} seconds...", seconds);
+                CheckRetrySleep(inst_id, sleep) => {
+                    // each attempt of a retrying group shows all of it's children, so
+                    // put the retry on it's own line in the tree
+                    if self.find_check_by_id(inst_id).is_group {
+                        show_tree(&path_stack);
+                        print!("|  ");
+                    }
+                    let msg = format!("  Sleep {}...", sleep);
                     print!("  {}", msg.bright_yellow());
                     let _ = stdout().flush();
                 }
@@ -170,7 +309,8 @@ impl DefaultOutputFormatter {
                     println!("  {}", msg.bright_yellow());
                 }
                 CheckFail(inst_id) => {
-                    let chk = &self.find_check_by_id_mut(inst_id);
+                    let chk = &mut self.find_check_by_id_mut(inst_id);
+                    chk.update_result(ChkDescResult::Fail);
                     if chk.is_group {
                         show_tree(&path_stack);
                     }
@@ -184,21 +324,112 @@ impl DefaultOutputFormatter {
                     }
                     print!(" {}", "Pass".truecolor(00, 200, 0).bold());
                 }
-                CheckError(inst_id) => {
-                    let chk = &self.find_check_by_id_mut(inst_id);
+                CheckError(inst_id, error) => {
+                    let chk = &mut self.find_check_by_id_mut(inst_id);
+                    chk.update_result(ChkDescResult::Error);
                     if chk.is_group {
                         show_tree(&path_stack);
                     }
-                    print!(" {}", "Error".red());
+                    // ex: Error: Timed out after 5s
+                    print!(" {} {}", "Error:".red(), error.red());
                 }
-                Term(filename) => {
+                CheckSkip(inst_id) => {
+                    let chk = &mut self.find_check_by_id_mut(inst_id);
+                    chk.update_result(ChkDescResult::Skipped);
+                    if chk.is_group {
+                        show_tree(&path_stack);
+                    }
+                    print!(" {}", "Skipped".yellow());
+                }
+                CheckWaitTick(inst_id, iteration, elapsed, timeout) => {
+                    self.waited.insert(inst_id);
+                    let remaining = Duration::from_secs(timeout.saturating_sub(elapsed).as_secs());
+                    let msg = format!(
+                        "  Waiting, {} left (iteration {})",
+                        humantime::format_duration(remaining),
+                        iteration
+                    );
+                    // clear the previous countdown
+                    print!("\r\x1b[K");
+                    show_tree(&path_stack);
+                    print!("{}", msg.bright_yellow());
+                    let _ = stdout().flush();
+                    waiting = true;
+                }
+                CheckOutput(inst_id, _) => {
+                    debug!("Check {} finished with output", inst_id);
+                }
+                HookStart(_inst_id, hook_name, cmd) => {
+                    debug!("Running {} hook [{}]", hook_name, cmd);
+                }
+                HookFinish(_inst_id, hook) => {
+                    // hooks run before a check starts or after it finishes, so they
+                    // get their own lines under the check
+                    show_tree(&path_stack);
+                    let status = match &hook.error {
+                        Some(e) => e.red(),
+                        None => "ok".truecolor(0, 200, 0),
+                    };
                     println!(
-                        "* Finished running tests from {}",
-                        filename.unwrap_or("<no file>".to_string())
+                        "   [{}] {} {} [{}μs]",
+                        hook.hook_name.cyan(),
+                        hook.cmd,
+                        status,
+                        hook.duration.as_micros()
                     );
-                    break;
+                    let out = &hook.process_out;
+                    for line in out
+                        .stdout
+                        .iter()
+                        .chain(out.stderr.iter())
+                        .flat_map(|o| o.lines())
+                    {
+                        show_tree(&path_stack);
+                        println!("     {}", line.dimmed());
+                    }
+                }
+                Term(filename) => {
+                    let filename = filename.unwrap_or("<no file>".to_string());
+                    println!("* Finished running tests from {}", filename);
+                    let summary = self.summarize_file(filename);
+                    self.files.push(summary);
+                }
+                AllStart => {
+                    debug!("Starting run");
+                }
+                AllFinish(wall_time) => {
+                    self.wall_time = Some(wall_time);
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predikit::formatters::test_utils::run_source_to_events;
+
+    #[test]
+    fn test_summarize_wait_groups() {
+        let source = r#"
+            wait until_pass wait_for: d(1s) interval: d(100ms) {
+                test exists? { path: "/" }
+                test exists? { path: "/" }
+            }
+            all {
+                test exists? { path: "/" }
+                test shell { cmd: "false" }
+            }
+        "#;
+        let mut formatter = DefaultOutputFormatter::default();
+        formatter.process_events(run_source_to_events(source));
+
+        // the wait group is counted instead of it's silent children
+        let summary = &formatter.files[0];
+        assert_eq!(
+            (2, 1, 0, 0),
+            (summary.pass, summary.fail, summary.error, summary.skipped)
+        );
+    }
+}
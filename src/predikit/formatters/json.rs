@@ -1,212 +1,335 @@
 // Copyright (c) 2025 Dave Parfitt
 
 use crate::predikit::data::events::ChkLifecycleEvent::*;
-use crate::predikit::data::events::{ChkDesc, ChkDescMap, ChkDescResult, ChkLifecycleEvent};
-use crate::predikit::data::instance::ChkInstId;
-use colored::Colorize;
+use crate::predikit::data::events::{ChkDesc, ChkDescMap, ChkHookRun, ChkLifecycleEvent};
+use crate::predikit::data::instance::{ChkInstId, ChkProcessOut, TITLE};
 use log::debug;
-use std::io::stdout;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
 use std::io::Write;
+use std::time::Duration;
 
-use super::{FormatterConfig, OutputFormatter};
+use super::{output_or_stdout, root_checks, FormatterConfig, FormatterOutput, OutputFormatter};
 
-enum PathType {
-    Group,
-    Check,
-}
+const PASS: &str = "pass";
+const FAIL: &str = "fail";
+const ERROR: &str = "error";
+const SKIPPED: &str = "skipped";
 
-pub struct JsonOutputFormatter {
-    config: FormatterConfig,
-    chk_inst_map: ChkDescMap,
+#[derive(Default)]
+struct ChkAttempt {
+    result: Option<&'static str>,
+    duration: Option<Duration>,
+    error: Option<String>,
+    output: Option<ChkProcessOut>,
 }
 
-impl Default for JsonOutputFormatter {
-    fn default() -> Self {
-        Self {
-            config: FormatterConfig::default(),
-            chk_inst_map: ChkDescMap::new(),
-        }
-    }
+#[derive(Default)]
+struct ChkRun {
+    attempts: Vec<ChkAttempt>,
+    hooks: Vec<Value>,
 }
 
-fn show_tree(path_stack: &Vec<PathType>) {
-    path_stack.iter().for_each(|segment| match segment {
-        PathType::Check => print!("->"),
-        PathType::Group => print!("|  "),
-    });
+/// Machine readable output. By default this is a single JSON document with the result
+/// tree of every file, written once all checks have finished. With ndjson, a JSON object
+/// is written on it's own line as each file starts and finishes, and as each check
+/// attempt and hook finishes.
+#[derive(Default)]
+pub struct JsonOutputFormatter {
+    ndjson: bool,
+    out: Option<FormatterOutput>,
+    chk_inst_map: ChkDescMap,
+    filename: Option<String>,
+    runs: HashMap<ChkInstId, ChkRun>,
+    files: Vec<Value>,
+    wall_time: Option<Duration>,
 }
 
 impl OutputFormatter for JsonOutputFormatter {
+    // there's no color in json
     fn init(&mut self, cfg: FormatterConfig) {
-        self.config = cfg;
+        self.out = cfg.output;
     }
 
     fn process_events(&mut self, receiver: std::sync::mpsc::Receiver<ChkLifecycleEvent>) {
         self.process_loop(receiver);
     }
 
-    fn term(&mut self) {}
+    fn term(&mut self) {
+        if !self.ndjson {
+            let doc = serde_json::to_string_pretty(&self.document()).unwrap();
+            let out = output_or_stdout(&mut self.out);
+            let _ = writeln!(out, "{}", doc);
+            let _ = out.flush();
+        }
+    }
+}
+
+fn micros(d: Duration) -> u64 {
+    d.as_micros() as u64
+}
+
+// skipped checks don't count as a pass or a fail
+fn all_pass<'a>(results: impl IntoIterator<Item = &'a Value>) -> &'static str {
+    let all_pass = results
+        .into_iter()
+        .all(|r| r.as_str().is_some_and(|r| r == PASS || r == SKIPPED));
+    if all_pass {
+        PASS
+    } else {
+        FAIL
+    }
+}
+
+fn hook_value(hook: &ChkHookRun) -> Value {
+    json!({
+        "hook": hook.hook_name,
+        "cmd": hook.cmd,
+        "exit_code": hook.process_out.exit_code,
+        "stdout": hook.process_out.stdout,
+        "stderr": hook.process_out.stderr,
+        "duration_us": micros(hook.duration),
+        "error": hook.error,
+    })
 }
 
 impl JsonOutputFormatter {
-    pub fn new(config: FormatterConfig) -> Self {
+    pub fn new_ndjson() -> Self {
         Self {
-            config,
-            chk_inst_map: ChkDescMap::new(),
+            ndjson: true,
+            ..Self::default()
         }
     }
 
-    fn find_check_by_id_mut(&mut self, id: ChkInstId) -> &mut ChkDesc {
-        self.chk_inst_map
-            .iter_mut()
-            .find(|(_, chk)| chk.instance_id == id)
-            .unwrap()
-            .1
+    fn run_mut(&mut self, id: ChkInstId) -> &mut ChkRun {
+        self.runs.entry(id).or_default()
     }
 
-    fn find_check_by_id(&self, id: ChkInstId) -> &ChkDesc {
-        self.chk_inst_map
-            .iter()
-            .find(|(_, chk)| chk.instance_id == id)
-            .unwrap()
-            .1
+    fn last_attempt_mut(&mut self, id: ChkInstId) -> Option<&mut ChkAttempt> {
+        self.run_mut(id).attempts.last_mut()
     }
 
-    fn get_root_checks(&self) -> Vec<&ChkDesc> {
-        self.chk_inst_map
-            .iter()
-            .filter(|(_, chk)| chk.is_root)
-            .map(|(_, chk)| chk)
-            .collect()
+    fn set_result(&mut self, id: ChkInstId, result: &'static str) {
+        if let Some(attempt) = self.last_attempt_mut(id) {
+            attempt.result = Some(result);
+        }
     }
 
-    fn fancy_params(&self, chk: &ChkDesc) -> String {
-        chk.fn_desc
-            .formal_params
-            .iter()
-            .map(|param| {
-                let param_name = param.0.clone();
-                let param_value = chk.actual_params.get(&param_name).unwrap();
-                format!("{}: {}", param_name, param_value.value_as_string())
-            })
-            .collect::<Vec<String>>()
-            .join(", ")
+    fn write_line(&mut self, mut line: Map<String, Value>, event: &str) {
+        line.insert("event".to_owned(), event.into());
+        line.insert("file".to_owned(), self.filename.clone().into());
+        self.write_value(Value::Object(line));
     }
 
-    pub fn process_events(&mut self, receiver: std::sync::mpsc::Receiver<ChkLifecycleEvent>) {
-        self.process_loop(receiver);
-        self.finish_processing_events();
+    fn write_value(&mut self, v: Value) {
+        let out = output_or_stdout(&mut self.out);
+        let _ = writeln!(out, "{}", v);
+        let _ = out.flush();
+    }
+
+    fn get_parent_id(&self, id: ChkInstId) -> Option<ChkInstId> {
+        self.chk_inst_map
+            .values()
+            .find(|chk| chk.children.contains(&id))
+            .map(|chk| chk.instance_id)
     }
 
-    fn finish_processing_events(&mut self) {
-        let root_checks = self.get_root_checks();
-        debug!("{:#?}", root_checks);
-        let all_pass = root_checks
+    // A check without it's children. The result and duration are from the last attempt
+    fn check_value(&self, chk: &ChkDesc) -> Map<String, Value> {
+        let run = self.runs.get(&chk.instance_id);
+        let last = run.and_then(|r| r.attempts.last());
+        let output = last.and_then(|a| a.output.as_ref());
+        let params: Map<String, Value> = chk
+            .actual_params
             .iter()
-            .all(|chk| matches!(chk.result, Some(ChkDescResult::Pass)));
-        if all_pass {
-            println!("{}", "All root checks passed".truecolor(0, 200, 0));
-        } else {
-            let some_pass = root_checks
-                .iter()
-                .any(|chk| matches!(chk.result, Some(ChkDescResult::Pass)));
-            if some_pass {
-                println!("{}", "Some root checks failed".truecolor(200, 0, 0));
-            } else {
-                println!("{}", "All root checks failed".truecolor(200, 0, 0));
-            }
+            .map(|(name, p)| (name.clone(), p.value.to_template_value()))
+            .collect();
+        let loop_vars: Map<String, Value> = chk
+            .loop_vars
+            .iter()
+            .map(|(name, v)| (name.clone(), v.to_template_value()))
+            .collect();
+        let v = json!({
+            "id": chk.instance_id,
+            "check": chk.fn_desc.fn_name,
+            "title": chk.actual_params.get(TITLE).map(|t| t.get_string()),
+            "group": chk.is_group,
+            "negated": chk.negated,
+            "params": params,
+            "loop_vars": loop_vars,
+            "result": last.and_then(|a| a.result),
+            "duration_us": last.and_then(|a| a.duration).map(micros),
+            "error": last.and_then(|a| a.error.clone()),
+            "exit_code": output.and_then(|o| o.exit_code),
+            "stdout": output.and_then(|o| o.stdout.clone()),
+            "stderr": output.and_then(|o| o.stderr.clone()),
+            "attempts": run.map_or(0, |r| r.attempts.len()),
+            "hooks": run.map_or(vec![], |r| r.hooks.clone()),
+        });
+        match v {
+            Value::Object(m) => m,
+            _ => unreachable!(),
         }
     }
 
-    fn get_title(&self, chk: &ChkDesc) -> String {
-        let p = chk.actual_params.get("title");
-        if let Some(p) = p {
-            format!("[{}] ", p.get_string())
-        } else {
-            "".to_string()
-        }
+    fn check_tree(&self, chk: &ChkDesc) -> Value {
+        let mut v = self.check_value(chk);
+        let children: Vec<Value> = chk
+            .children
+            .iter()
+            .filter_map(|id| self.chk_inst_map.get(id))
+            .map(|child| self.check_tree(child))
+            .collect();
+        v.insert("children".to_owned(), children.into());
+        Value::Object(v)
     }
 
-    fn process_loop(&mut self, receiver: std::sync::mpsc::Receiver<ChkLifecycleEvent>) {
-        let mut path_stack: Vec<PathType> = vec![];
+    fn file_value(&self) -> Value {
+        let checks: Vec<Value> = root_checks(&self.chk_inst_map)
+            .into_iter()
+            .map(|chk| self.check_tree(chk))
+            .collect();
+        json!({
+            "file": self.filename,
+            "result": all_pass(checks.iter().map(|c| &c["result"])),
+            "checks": checks,
+        })
+    }
 
+    fn document(&self) -> Value {
+        json!({
+            "result": all_pass(self.files.iter().map(|f| &f["result"])),
+            "duration_us": self.wall_time.map(micros),
+            "files": self.files,
+        })
+    }
+
+    fn process_loop(&mut self, receiver: std::sync::mpsc::Receiver<ChkLifecycleEvent>) {
         for event in receiver.iter() {
             match event {
                 Init(checks, filename) => {
-                    println!(
-                        "\n* Running tests from {}:",
-                        filename.unwrap_or("<no file>".to_string())
-                    );
                     self.chk_inst_map = checks;
-                    debug!("CHECKS: {:#?}", self.chk_inst_map);
+                    self.filename = filename;
+                    self.runs.clear();
+                    if self.ndjson {
+                        self.write_line(Map::new(), "file_start");
+                    }
                 }
-
                 CheckStart(inst_id) => {
-                    let chk = self.find_check_by_id(inst_id);
-                    let title = self.get_title(chk).blue();
-                    let negate = if chk.negated { " not " } else { "" };
-
-                    if chk.is_group {
-                        path_stack.push(PathType::Group);
-                    } else {
-                        path_stack.push(PathType::Check);
+                    self.run_mut(inst_id).attempts.push(ChkAttempt::default());
+                }
+                CheckPass(inst_id) => self.set_result(inst_id, PASS),
+                CheckFail(inst_id) => self.set_result(inst_id, FAIL),
+                CheckError(inst_id, e) => {
+                    self.set_result(inst_id, ERROR);
+                    if let Some(attempt) = self.last_attempt_mut(inst_id) {
+                        attempt.error = Some(e);
                     }
-                    show_tree(&path_stack);
-                    print!(
-                        " {}{}[{}] ",
-                        title,
-                        negate.purple(),
-                        chk.fn_desc.fn_name.blue()
-                    );
-                    print!("{}", self.fancy_params(chk));
-                    if chk.is_group {
-                        println!();
+                }
+                CheckOutput(inst_id, output) => {
+                    if let Some(attempt) = self.last_attempt_mut(inst_id) {
+                        attempt.output = Some(output);
                     }
                 }
+                CheckSkip(inst_id) => self.set_result(inst_id, SKIPPED),
                 CheckFinish(inst_id, duration) => {
-                    let _chk = &self.find_check_by_id_mut(inst_id);
-                    println!(" [{}μs]", duration.as_micros());
-                    let _ = path_stack.pop();
-                }
-                CheckRetrySleep(_inst_id, seconds) => {
-                    let msg = format!("  Sleep {} seconds...", seconds);
-                    print!("  {}", msg.bright_yellow());
-                    let _ = stdout().flush();
+                    if let Some(attempt) = self.last_attempt_mut(inst_id) {
+                        attempt.duration = Some(duration);
+                    }
+                    if self.ndjson {
+                        if let Some(chk) = self.chk_inst_map.get(&inst_id) {
+                            let mut line = self.check_value(chk);
+                            line.insert("parent".to_owned(), self.get_parent_id(inst_id).into());
+                            self.write_line(line, "check");
+                        }
+                    }
                 }
-                CheckRetry(_inst_id, attempt) => {
-                    let msg = format!("  Retry {}", attempt);
-                    println!("  {}", msg.bright_yellow());
+                HookFinish(inst_id, hook) => {
+                    let hook = hook_value(&hook);
+                    if self.ndjson {
+                        if let Value::Object(mut line) = hook.clone() {
+                            line.insert("id".to_owned(), inst_id.into());
+                            self.write_line(line, "hook");
+                        }
+                    }
+                    self.run_mut(inst_id).hooks.push(hook);
                 }
-                CheckFail(inst_id) => {
-                    let chk = &self.find_check_by_id_mut(inst_id);
-                    if chk.is_group {
-                        show_tree(&path_stack);
+                Term(_filename) => {
+                    let file = self.file_value();
+                    if self.ndjson {
+                        let mut line = Map::new();
+                        line.insert("result".to_owned(), file["result"].clone());
+                        self.write_line(line, "file_finish");
                     }
-                    print!(" {}", "Fail".truecolor(200, 0, 0).bold());
+                    self.files.push(file);
                 }
-                CheckPass(inst_id) => {
-                    let chk = &mut self.find_check_by_id_mut(inst_id);
-                    chk.update_result(ChkDescResult::Pass);
-                    if chk.is_group {
-                        show_tree(&path_stack);
+                AllStart => {
+                    if self.ndjson {
+                        self.write_value(json!({ "event": "run_start" }));
                     }
-                    print!(" {}", "Pass".truecolor(00, 200, 0).bold());
                 }
-                CheckError(inst_id) => {
-                    let chk = &self.find_check_by_id_mut(inst_id);
-                    if chk.is_group {
-                        show_tree(&path_stack);
+                AllFinish(wall_time) => {
+                    self.wall_time = Some(wall_time);
+                    if self.ndjson {
+                        let doc = self.document();
+                        self.write_value(json!({
+                            "event": "run_finish",
+                            "result": doc["result"],
+                            "duration_us": doc["duration_us"],
+                        }));
                     }
-                    print!(" {}", "Error".red());
                 }
-                Term(filename) => {
-                    println!(
-                        "* Finished running tests from {}",
-                        filename.unwrap_or("<no file>".to_string())
-                    );
-                    break;
+                CheckRetry(..) | CheckRetrySleep(..) | CheckWaitTick(..) | HookStart(..) => {
+                    debug!("Not shown in json: {:?}", event);
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predikit::formatters::test_utils::{run_source_to_events, TEST_FILE};
+
+    #[test]
+    fn test_json_document() {
+        let source = r#"
+            all {
+                title: "Host"
+                test exists? { path: "/" }
+                test shell { cmd: "exit 1" on_fail: "echo failed" }
+            }
+            @test shell { cmd: "true" retries: 1 retry_delay: d(1s) }
+        "#;
+        let mut formatter = JsonOutputFormatter::default();
+        formatter.process_events(run_source_to_events(source));
+        let doc = formatter.document();
+
+        assert_eq!(FAIL, doc["result"]);
+        let file = &doc["files"][0];
+        assert_eq!(TEST_FILE, file["file"]);
+        assert_eq!(FAIL, file["result"]);
+
+        let group = &file["checks"][0];
+        assert_eq!("all", group["check"]);
+        assert_eq!("Host", group["title"]);
+        assert_eq!(FAIL, group["result"]);
+        assert!(group["duration_us"].is_u64());
+
+        let children = group["children"].as_array().unwrap();
+        assert_eq!(2, children.len());
+        assert_eq!(PASS, children[0]["result"]);
+        assert_eq!("exit 1", children[1]["params"]["cmd"]);
+        assert_eq!(FAIL, children[1]["result"]);
+        assert_eq!(1, children[1]["exit_code"]);
+        let hook = &children[1]["hooks"][0];
+        assert_eq!("on_fail", hook["hook"]);
+        assert_eq!("failed\n", hook["stdout"]);
+        assert_eq!(0, hook["exit_code"]);
+
+        let retrying = &file["checks"][1];
+        assert_eq!(PASS, retrying["result"]);
+        assert_eq!(1, retrying["attempts"]);
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright (c) 2025 Dave Parfitt
+
+use crate::predikit::data::events::ChkLifecycleEvent::*;
+use crate::predikit::data::events::{ChkDesc, ChkDescMap, ChkDescResult, ChkLifecycleEvent};
+use crate::predikit::data::instance::{ChkInstId, ChkProcessOut};
+use log::debug;
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Duration;
+
+use super::{
+    check_description, output_or_stdout, root_checks, FormatterConfig, FormatterOutput,
+    OutputFormatter,
+};
+
+// The last attempt of a check
+#[derive(Default)]
+struct ChkCase {
+    result: Option<ChkDescResult>,
+    duration: Duration,
+    error: Option<String>,
+    output: Option<ChkProcessOut>,
+    // a wait group, its children run without events
+    waited: bool,
+}
+
+#[derive(Default)]
+struct SuiteCounts {
+    tests: usize,
+    failures: usize,
+    errors: usize,
+    skipped: usize,
+    time: Duration,
+}
+
+impl SuiteCounts {
+    fn add(&mut self, other: &SuiteCounts) {
+        self.tests += other.tests;
+        self.failures += other.failures;
+        self.errors += other.errors;
+        self.skipped += other.skipped;
+        self.time += other.time;
+    }
+
+    fn attrs(&self) -> String {
+        format!(
+            r#"tests="{}" failures="{}" errors="{}" skipped="{}" time="{}""#,
+            self.tests,
+            self.failures,
+            self.errors,
+            self.skipped,
+            seconds(self.time)
+        )
+    }
+}
+
+/// JUnit XML for CI systems. Each file is a `<testsuite>`, and each root check is a
+/// `<testcase>`, so the report fails exactly when the run does. A fail or an error
+/// includes the output of the check, and a group lists the results of its children
+/// in `<system-out>`.
+/// The report is written once all checks have finished.
+#[derive(Default)]
+pub struct JUnitOutputFormatter {
+    out: Option<FormatterOutput>,
+    chk_inst_map: ChkDescMap,
+    filename: Option<String>,
+    cases: HashMap<ChkInstId, ChkCase>,
+    suites: Vec<String>,
+    totals: SuiteCounts,
+}
+
+impl OutputFormatter for JUnitOutputFormatter {
+    fn init(&mut self, cfg: FormatterConfig) {
+        self.out = cfg.output;
+    }
+
+    fn process_events(&mut self, receiver: std::sync::mpsc::Receiver<ChkLifecycleEvent>) {
+        self.process_loop(receiver);
+    }
+
+    fn term(&mut self) {
+        let report = self.report();
+        let out = output_or_stdout(&mut self.out);
+        let _ = out.write_all(report.as_bytes());
+        let _ = out.flush();
+    }
+}
+
+fn seconds(d: Duration) -> String {
+    format!("{:.3}", d.as_secs_f64())
+}
+
+// Escape text and attribute values. Control characters aren't allowed in XML 1.0,
+// even escaped, so they're dropped.
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn result_name(result: &ChkDescResult) -> &'static str {
+    match result {
+        ChkDescResult::Pass => "pass",
+        ChkDescResult::Fail => "fail",
+        ChkDescResult::Error => "error",
+        ChkDescResult::Skipped => "skipped",
+    }
+}
+
+fn output_text(output: Option<&ChkProcessOut>) -> String {
+    let mut text = String::new();
+    let Some(output) = output else {
+        return text;
+    };
+    if let Some(code) = output.exit_code {
+        text.push_str(&format!("exit code: {}\n", code));
+    }
+    for (name, s) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        if let Some(s) = s.as_deref().filter(|s| !s.trim().is_empty()) {
+            text.push_str(&format!("{}:\n{}\n", name, s.trim_end()));
+        }
+    }
+    text
+}
+
+impl JUnitOutputFormatter {
+    fn case_mut(&mut self, id: ChkInstId) -> &mut ChkCase {
+        self.cases.entry(id).or_default()
+    }
+
+    // a check that never reported a result didn't run
+    fn result(&self, chk: &ChkDesc) -> ChkDescResult {
+        self.cases
+            .get(&chk.instance_id)
+            .and_then(|c| c.result.clone())
+            .unwrap_or(ChkDescResult::Skipped)
+    }
+
+    // The results of the children of a group, one line each and indented by depth, followed
+    // by the output of the checks that failed. ex:
+    //   fail shell cmd: false
+    //     exit code: 1
+    // The children of a wait group run without events, so they aren't shown.
+    fn child_results(&self, chk: &ChkDesc, depth: usize, text: &mut String) {
+        let case = self.cases.get(&chk.instance_id);
+        if case.is_some_and(|c| c.waited) {
+            return;
+        }
+        let indent = "  ".repeat(depth);
+        for child in chk
+            .children
+            .iter()
+            .filter_map(|id| self.chk_inst_map.get(id))
+        {
+            let result = self.result(child);
+            text.push_str(&format!(
+                "{}{} {}\n",
+                indent,
+                result_name(&result),
+                check_description(child)
+            ));
+            if matches!(result, ChkDescResult::Fail | ChkDescResult::Error) {
+                let child_case = self.cases.get(&child.instance_id);
+                if let Some(error) = child_case.and_then(|c| c.error.as_deref()) {
+                    text.push_str(&format!("{}  error: {}\n", indent, error));
+                }
+                for line in output_text(child_case.and_then(|c| c.output.as_ref())).lines() {
+                    text.push_str(&format!("{}  {}\n", indent, line));
+                }
+            }
+            self.child_results(child, depth + 1, text);
+        }
+    }
+
+    fn testcase(&self, chk: &ChkDesc, classname: &str, counts: &mut SuiteCounts) -> String {
+        let case = self.cases.get(&chk.instance_id);
+        let duration = case.map(|c| c.duration).unwrap_or_default();
+        let output = output_text(case.and_then(|c| c.output.as_ref()));
+        counts.tests += 1;
+
+        let body = match self.result(chk) {
+            ChkDescResult::Pass => None,
+            ChkDescResult::Fail => {
+                counts.failures += 1;
+                Some(format!(
+                    "      <failure message=\"check failed\">{}</failure>\n",
+                    xml_escape(&output)
+                ))
+            }
+            ChkDescResult::Error => {
+                counts.errors += 1;
+                let message = case.and_then(|c| c.error.as_deref()).unwrap_or_default();
+                Some(format!(
+                    "      <error message=\"{}\">{}</error>\n",
+                    xml_escape(message),
+                    xml_escape(&output)
+                ))
+            }
+            ChkDescResult::Skipped => {
+                counts.skipped += 1;
+                Some("      <skipped/>\n".to_owned())
+            }
+        };
+
+        // a failing child of a passing `any` is only shown here, it doesn't fail the testcase
+        let mut children = String::new();
+        if chk.is_group {
+            self.child_results(chk, 0, &mut children);
+        }
+        let body = match (body, children.is_empty()) {
+            (body, true) => body,
+            (body, false) => Some(format!(
+                "{}      <system-out>{}</system-out>\n",
+                body.unwrap_or_default(),
+                xml_escape(&children)
+            )),
+        };
+
+        let open = format!(
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{}\"",
+            xml_escape(&check_description(chk)),
+            xml_escape(classname),
+            seconds(duration)
+        );
+        match body {
+            Some(body) => format!("{}>\n{}    </testcase>\n", open, body),
+            None => format!("{}/>\n", open),
+        }
+    }
+
+    fn finish_suite(&mut self) {
+        let name = self.filename.clone().unwrap_or("<no file>".to_string());
+        let mut counts = SuiteCounts::default();
+        let cases: String = root_checks(&self.chk_inst_map)
+            .into_iter()
+            .map(|chk| self.testcase(chk, &name, &mut counts))
+            .collect();
+        // the time of the file is the time of it's root checks, which include their children
+        counts.time = self
+            .chk_inst_map
+            .values()
+            .filter(|chk| chk.is_root)
+            .filter_map(|chk| self.cases.get(&chk.instance_id))
+            .map(|c| c.duration)
+            .sum();
+
+        self.suites.push(format!(
+            "  <testsuite name=\"{}\" {}>\n{}  </testsuite>\n",
+            xml_escape(&name),
+            counts.attrs(),
+            cases
+        ));
+        self.totals.add(&counts);
+    }
+
+    fn report(&self) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"predikit\" {}>\n{}</testsuites>\n",
+            self.totals.attrs(),
+            self.suites.concat()
+        )
+    }
+
+    fn process_loop(&mut self, receiver: std::sync::mpsc::Receiver<ChkLifecycleEvent>) {
+        for event in receiver.iter() {
+            match event {
+                Init(checks, filename) => {
+                    self.chk_inst_map = checks;
+                    self.filename = filename;
+                    self.cases.clear();
+                }
+                CheckStart(inst_id) => {
+                    *self.case_mut(inst_id) = ChkCase::default();
+                }
+                CheckPass(inst_id) => self.case_mut(inst_id).result = Some(ChkDescResult::Pass),
+                CheckFail(inst_id) => self.case_mut(inst_id).result = Some(ChkDescResult::Fail),
+                CheckError(inst_id, e) => {
+                    let case = self.case_mut(inst_id);
+                    case.result = Some(ChkDescResult::Error);
+                    case.error = Some(e);
+                }
+                CheckSkip(inst_id) => {
+                    self.case_mut(inst_id).result = Some(ChkDescResult::Skipped);
+                }
+                CheckOutput(inst_id, output) => self.case_mut(inst_id).output = Some(output),
+                CheckFinish(inst_id, duration) => self.case_mut(inst_id).duration = duration,
+                CheckWaitTick(inst_id, ..) => self.case_mut(inst_id).waited = true,
+                Term(_filename) => self.finish_suite(),
+                // checks in parallel groups overlap, so the wall time of the run is
+                // usually less than the time of it's files added up
+                AllFinish(wall_time) => self.totals.time = wall_time,
+                AllStart | CheckRetry(..) | CheckRetrySleep(..) | HookStart(..)
+                | HookFinish(..) => {
+                    debug!("Not shown in junit: {:?}", event);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predikit::formatters::test_utils::run_source_to_events;
+
+    #[test]
+    fn test_junit_report() {
+        let source = r#"
+            all {
+                title: "Host"
+                test exists? { path: "/" }
+                test shell { cmd: "echo 'a < b' >&2; exit 1" }
+            }
+            test shell { cmd: "true" when: "false" }
+        "#;
+        let mut formatter = JUnitOutputFormatter::default();
+        formatter.process_events(run_source_to_events(source));
+        let report = formatter.report();
+
+        assert!(report.starts_with("<?xml"));
+        assert!(report.contains(
+            r#"<testsuite name="test.pk" tests="2" failures="1" errors="0" skipped="1""#
+        ));
+        assert!(report.contains(r#"<testcase name="[Host] all" classname="test.pk""#));
+        assert!(report.contains("<failure message=\"check failed\"></failure>"));
+        // the children of the group, with the output of the one that failed
+        assert!(report.contains(
+            "<system-out>pass exists? path: /\nfail shell cmd: echo &apos;a &lt; b&apos; &gt;&amp;2; exit 1\n  exit code: 1\n  stderr:\n  a &lt; b\n</system-out>"
+        ));
+        assert!(report.contains("<skipped/>"));
+    }
+
+    #[test]
+    fn test_junit_group_verdicts() {
+        let source = r#"
+            wait until_pass wait_for: d(100ms) interval: d(50ms) {
+                test shell { cmd: "false" }
+            }
+            any {
+                test shell { cmd: "false" }
+                test exists? { path: "/" }
+            }
+            not all {
+                test exists? { path: "/" }
+            }
+        "#;
+        let mut formatter = JUnitOutputFormatter::default();
+        formatter.process_events(run_source_to_events(source));
+        let report = formatter.report();
+
+        // the report fails exactly when the run does, so the failing child of the
+        // passing `any` isn't a failure, and the failing `not all` is
+        assert!(report.contains(
+            r#"<testsuite name="test.pk" tests="3" failures="1" errors="1" skipped="0""#
+        ));
+        assert!(report.contains("<error message=\"Timeout after"));
+        let any = report
+            .split("<testcase ")
+            .find(|case| case.starts_with(r#"name="any""#))
+            .unwrap();
+        assert!(!any.contains("<failure"));
+        assert!(any.contains("<system-out>fail shell cmd: false\n  exit code: 1\npass exists? path: /\n</system-out>"));
+        let not_all = report
+            .split("<testcase ")
+            .find(|case| case.starts_with(r#"name="not all""#))
+            .unwrap();
+        assert!(not_all.contains("<failure message=\"check failed\">"));
+        assert!(not_all.contains("<system-out>pass exists? path: /\n</system-out>"));
+    }
+}
@@ -0,0 +1,315 @@
+// Copyright (c) 2025 Dave Parfitt
+
+use crate::predikit::data::events::ChkLifecycleEvent::*;
+use crate::predikit::data::events::{ChkDesc, ChkDescMap, ChkDescResult, ChkLifecycleEvent};
+use crate::predikit::data::instance::{ChkInstId, ChkProcessOut};
+use log::debug;
+use std::collections::HashMap;
+use std::io::Write;
+
+use super::{
+    check_description, output_or_stdout, root_checks, FormatterConfig, FormatterOutput,
+    OutputFormatter,
+};
+
+// subtests are indented 4 spaces, and YAML diagnostics 2 spaces past their test point
+const INDENT: &str = "    ";
+
+#[derive(Default)]
+struct ChkAttempt {
+    result: Option<ChkDescResult>,
+    error: Option<String>,
+    output: Option<ChkProcessOut>,
+    // the sleep before the next attempt
+    retry_sleep: Option<String>,
+}
+
+/// TAP version 14. Each file is a subtest, and so is each group, with it's children as
+/// the tests of the subtest. A check's earlier attempts are listed as retries in it's YAML
+/// diagnostics. A file is written once all of it's checks have finished.
+#[derive(Default)]
+pub struct TapOutputFormatter {
+    out: Option<FormatterOutput>,
+    chk_inst_map: ChkDescMap,
+    filename: Option<String>,
+    attempts: HashMap<ChkInstId, Vec<ChkAttempt>>,
+    files: usize,
+}
+
+impl OutputFormatter for TapOutputFormatter {
+    fn init(&mut self, cfg: FormatterConfig) {
+        self.out = cfg.output;
+    }
+
+    fn process_events(&mut self, receiver: std::sync::mpsc::Receiver<ChkLifecycleEvent>) {
+        self.write_lines(&["TAP version 14".to_owned()]);
+        self.process_loop(receiver);
+    }
+
+    // the plan comes last, the number of files isn't known until every file has run
+    fn term(&mut self) {
+        let plan = format!("1..{}", self.files);
+        self.write_lines(&[plan]);
+    }
+}
+
+// `#` starts a directive, ex: # SKIP, so it's escaped in descriptions
+fn escape_description(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('#', "\\#")
+        .replace('\n', " ")
+}
+
+// JSON strings are valid YAML, and take care of quoting and newlines
+fn yaml_str(s: &str) -> String {
+    serde_json::to_string(s).unwrap()
+}
+
+fn result_name(result: Option<&ChkDescResult>) -> &'static str {
+    match result {
+        Some(ChkDescResult::Pass) => "pass",
+        Some(ChkDescResult::Fail) => "fail",
+        Some(ChkDescResult::Error) => "error",
+        Some(ChkDescResult::Skipped) | None => "skipped",
+    }
+}
+
+fn diagnostics(attempts: &[ChkAttempt]) -> Vec<String> {
+    let mut diag = vec![];
+    let Some((last, retries)) = attempts.split_last() else {
+        return diag;
+    };
+
+    if let Some(e) = &last.error {
+        diag.push(format!("error: {}", yaml_str(e)));
+    }
+    let failed = matches!(
+        last.result,
+        Some(ChkDescResult::Fail) | Some(ChkDescResult::Error)
+    );
+    if let Some(output) = last.output.as_ref().filter(|_| failed) {
+        if let Some(code) = output.exit_code {
+            diag.push(format!("exit_code: {}", code));
+        }
+        for (name, s) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            if let Some(s) = s.as_deref().filter(|s| !s.trim().is_empty()) {
+                diag.push(format!("{}: {}", name, yaml_str(s)));
+            }
+        }
+    }
+
+    if !retries.is_empty() {
+        diag.push("retries:".to_owned());
+    }
+    for (i, attempt) in retries.iter().enumerate() {
+        diag.push(format!("  - attempt: {}", i + 1));
+        diag.push(format!(
+            "    result: {}",
+            result_name(attempt.result.as_ref())
+        ));
+        if let Some(e) = &attempt.error {
+            diag.push(format!("    error: {}", yaml_str(e)));
+        }
+        if let Some(sleep) = &attempt.retry_sleep {
+            diag.push(format!("    sleep: {}", yaml_str(sleep)));
+        }
+    }
+    diag
+}
+
+impl TapOutputFormatter {
+    fn last_attempt_mut(&mut self, id: ChkInstId) -> Option<&mut ChkAttempt> {
+        self.attempts.entry(id).or_default().last_mut()
+    }
+
+    fn write_lines(&mut self, lines: &[String]) {
+        let out = output_or_stdout(&mut self.out);
+        for line in lines {
+            let _ = writeln!(out, "{}", line);
+        }
+        let _ = out.flush();
+    }
+
+    // Add the test point for a check to lines, and return true if it's ok.
+    // A group is a subtest, with a test point for each child before it's own.
+    fn test_point(&self, chk: &ChkDesc, num: usize, indent: &str, lines: &mut Vec<String>) -> bool {
+        let description = escape_description(&check_description(chk));
+        if chk.is_group {
+            lines.push(format!("{}# Subtest: {}", indent, description));
+            let child_indent = format!("{}{}", indent, INDENT);
+            let children: Vec<&ChkDesc> = chk
+                .children
+                .iter()
+                .filter_map(|id| self.chk_inst_map.get(id))
+                .collect();
+            for (i, child) in children.iter().enumerate() {
+                self.test_point(child, i + 1, &child_indent, lines);
+            }
+            lines.push(format!("{}1..{}", child_indent, children.len()));
+        }
+
+        let attempts = self
+            .attempts
+            .get(&chk.instance_id)
+            .map_or(&[][..], |a| a.as_slice());
+        // a check that never reported a result didn't run
+        let (ok, directive) = match attempts.last().and_then(|a| a.result.as_ref()) {
+            Some(ChkDescResult::Pass) => (true, ""),
+            Some(ChkDescResult::Skipped) | None => (true, " # SKIP"),
+            Some(ChkDescResult::Fail) | Some(ChkDescResult::Error) => (false, ""),
+        };
+        lines.push(format!(
+            "{}{} {} - {}{}",
+            indent,
+            if ok { "ok" } else { "not ok" },
+            num,
+            description,
+            directive
+        ));
+
+        let diag = diagnostics(attempts);
+        if !diag.is_empty() {
+            lines.push(format!("{}  ---", indent));
+            lines.extend(diag.iter().map(|d| format!("{}  {}", indent, d)));
+            lines.push(format!("{}  ...", indent));
+        }
+        ok
+    }
+
+    fn file_lines(&self, num: usize) -> Vec<String> {
+        let name = escape_description(self.filename.as_deref().unwrap_or("<no file>"));
+        let mut lines = vec![format!("# Subtest: {}", name)];
+        let roots = root_checks(&self.chk_inst_map);
+        let mut ok = true;
+        for (i, chk) in roots.iter().enumerate() {
+            ok &= self.test_point(chk, i + 1, INDENT, &mut lines);
+        }
+        lines.push(format!("{}1..{}", INDENT, roots.len()));
+        lines.push(format!(
+            "{} {} - {}",
+            if ok { "ok" } else { "not ok" },
+            num,
+            name
+        ));
+        lines
+    }
+
+    fn process_loop(&mut self, receiver: std::sync::mpsc::Receiver<ChkLifecycleEvent>) {
+        for event in receiver.iter() {
+            match event {
+                Init(checks, filename) => {
+                    self.chk_inst_map = checks;
+                    self.filename = filename;
+                    self.attempts.clear();
+                }
+                CheckStart(inst_id) => {
+                    self.attempts
+                        .entry(inst_id)
+                        .or_default()
+                        .push(ChkAttempt::default());
+                }
+                CheckPass(inst_id) => {
+                    if let Some(attempt) = self.last_attempt_mut(inst_id) {
+                        attempt.result = Some(ChkDescResult::Pass);
+                    }
+                }
+                CheckFail(inst_id) => {
+                    if let Some(attempt) = self.last_attempt_mut(inst_id) {
+                        attempt.result = Some(ChkDescResult::Fail);
+                    }
+                }
+                CheckError(inst_id, e) => {
+                    if let Some(attempt) = self.last_attempt_mut(inst_id) {
+                        attempt.result = Some(ChkDescResult::Error);
+                        attempt.error = Some(e);
+                    }
+                }
+                CheckSkip(inst_id) => {
+                    if let Some(attempt) = self.last_attempt_mut(inst_id) {
+                        attempt.result = Some(ChkDescResult::Skipped);
+                    }
+                }
+                CheckOutput(inst_id, output) => {
+                    if let Some(attempt) = self.last_attempt_mut(inst_id) {
+                        attempt.output = Some(output);
+                    }
+                }
+                CheckRetrySleep(inst_id, sleep) => {
+                    if let Some(attempt) = self.last_attempt_mut(inst_id) {
+                        attempt.retry_sleep = Some(sleep.to_string());
+                    }
+                }
+                Term(_filename) => {
+                    self.files += 1;
+                    let lines = self.file_lines(self.files);
+                    self.write_lines(&lines);
+                }
+                AllStart | AllFinish(..) | CheckFinish(..) | CheckRetry(..) | CheckWaitTick(..)
+                | HookStart(..) | HookFinish(..) => {
+                    debug!("Not shown in tap: {:?}", event);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predikit::formatters::test_utils::{run_source_to_events, SharedOutput};
+
+    #[test]
+    fn test_tap_lines() {
+        let source = r#"
+            all {
+                title: "Host #1"
+                test exists? { path: "/" }
+                test not exists? { path: "/" }
+            }
+            @test shell {
+                cmd: "echo oops; exit 1"
+                retries: 2
+                retry_delay: d(1ms)
+            }
+            test exists? { path: "/" on_init: "exit 3" }
+        "#;
+        let out = SharedOutput::default();
+        let mut formatter = TapOutputFormatter::default();
+        formatter.init(FormatterConfig {
+            color: false,
+            output: Some(Box::new(out.clone())),
+        });
+        formatter.process_events(run_source_to_events(source));
+        formatter.term();
+
+        let lines: Vec<String> = out.contents().lines().map(|l| l.to_owned()).collect();
+        assert_eq!(
+            vec![
+                "TAP version 14",
+                "# Subtest: test.pk",
+                "    # Subtest: [Host \\#1] all",
+                "        ok 1 - exists? path: /",
+                "        not ok 2 - not exists? path: /",
+                "        1..2",
+                "    not ok 1 - [Host \\#1] all",
+                "    not ok 2 - shell cmd: echo oops; exit 1",
+                "      ---",
+                "      exit_code: 1",
+                "      stdout: \"oops\\n\"",
+                "      retries:",
+                "        - attempt: 1",
+                "          result: fail",
+                "          sleep: \"1ms\"",
+                "      ...",
+                "    not ok 3 - exists? path: /",
+                "      ---",
+                "      error: \"on_init hook exited with code 3\"",
+                "      ...",
+                "    1..3",
+                "not ok 1 - test.pk",
+                "1..1",
+            ],
+            lines
+        );
+    }
+}
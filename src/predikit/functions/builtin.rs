@@ -2,12 +2,26 @@
 
 use crate::predikit::data::instance::{ChkInstance, ChkResult, RunEnv};
 use crate::predikit::data::params::ChkActualParams;
-use crate::predikit::data::{ChkDef, FParamsBuilder};
+use crate::predikit::data::{ChkDef, ChkFn, ChkFormalParams, ChkParamType, FParamsBuilder};
 use crate::predikit::functions::builtin_fs::{
     cd_file_exists, cd_file_is_executable, cd_file_is_on_path, cd_shell,
 };
 use crate::predikit::functions::builtin_net::{cd_port_addr_open, cd_port_open};
 use log::debug;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Mutex;
+use std::thread;
+
+// the name of the param that holds n for threshold groups, ex: at_least(2) { ... }
+pub const THRESHOLD: &str = "n";
+// run the children of a group concurrently, ex: all { parallel: true ... }
+pub const PARALLEL: &str = "parallel";
+// stop running the children of all/any/none once the result of the group is known
+pub const SHORT_CIRCUIT: &str = "short_circuit";
+// the global config value set by --jobs, the max number of children of a group
+// that run at the same time
+pub const JOBS: &str = "jobs";
 
 pub fn define_builtins() -> Vec<ChkDef> {
     vec![
@@ -34,6 +48,8 @@ pub fn cd_true() -> ChkDef {
                 result: Ok(true),
                 process_out: None,
                 children_results: None,
+                skipped: false,
+                wait_stats: None,
             }
         },
         template_params: None,
@@ -52,6 +68,8 @@ pub fn cd_false() -> ChkDef {
                 result: Ok(false),
                 process_out: None,
                 children_results: None,
+                skipped: false,
+                wait_stats: None,
             }
         },
         template_params: None,
@@ -59,73 +77,251 @@ pub fn cd_false() -> ChkDef {
 }
 
 pub fn define_aggs() -> Vec<ChkDef> {
-    vec![cd_all(), cd_any(), cd_none()]
+    vec![
+        cd_all(),
+        cd_any(),
+        cd_none(),
+        cd_at_least(),
+        cd_at_most(),
+        cd_exactly(),
+    ]
 }
 
-fn agg_all(f: &[ChkResult]) -> bool {
-    f.iter().all(|x| *x.result.as_ref().unwrap())
+// a child that errors never counts as a pass (or as a fail for `none`).
+// Skipped children are filtered out before aggregating.
+fn agg_all(f: &[&ChkResult]) -> bool {
+    f.iter().all(|x| x.is_check_pass())
 }
 
-fn agg_any(f: &[ChkResult]) -> bool {
-    f.iter().any(|x| *x.result.as_ref().unwrap())
+fn agg_any(f: &[&ChkResult]) -> bool {
+    f.iter().any(|x| x.is_check_pass())
 }
 
-fn agg_none(f: &[ChkResult]) -> bool {
-    f.iter().all(|x| !*x.result.as_ref().unwrap())
+fn agg_none(f: &[&ChkResult]) -> bool {
+    f.iter().all(|x| x.is_check_fail())
+}
+
+fn agg_passed(f: &[&ChkResult]) -> usize {
+    f.iter().filter(|x| x.is_check_pass()).count()
 }
 
 enum AggType {
     All,
     Any,
     None,
+    AtLeast,
+    AtMost,
+    Exactly,
+}
+
+// `parallel: true` runs a group's children with up to --jobs workers, or all of them at
+// once if --jobs isn't set (checks mostly wait on processes and the network, not the cpu).
+// --jobs on it's own makes every group parallel, unless the group sets `parallel: false`.
+// The --jobs limit is for the whole run (RunEnv.job_slots), so nested parallel groups
+// still run at most --jobs checks at once.
+fn group_jobs(run_env: &RunEnv, params: &ChkActualParams) -> usize {
+    let global_jobs = run_env
+        .global_config
+        .get(JOBS)
+        .map(|p| p.get_int().max(1) as usize);
+    match params.get(PARALLEL).map(|p| p.get_bool()) {
+        Some(false) => 1,
+        Some(true) => global_jobs.unwrap_or(usize::MAX),
+        None => global_jobs.unwrap_or(1),
+    }
+}
+
+// A child result that decides the result of the group, so the rest of the
+// children don't need to run. ex: the first child of `all` that doesn't pass.
+// Threshold groups don't short circuit.
+type ShortCircuit = fn(&ChkResult) -> bool;
+
+fn short_circuit(agg_type: &AggType, params: &ChkActualParams) -> Option<ShortCircuit> {
+    if !params.get(SHORT_CIRCUIT).is_some_and(|p| p.get_bool()) {
+        return None;
+    }
+    match agg_type {
+        AggType::All => Some(|r| !r.is_check_pass() && !r.is_check_skipped()),
+        AggType::Any => Some(|r| r.is_check_pass()),
+        AggType::None => Some(|r| !r.is_check_fail() && !r.is_check_skipped()),
+        AggType::AtLeast | AggType::AtMost | AggType::Exactly => None,
+    }
+}
+
+fn run_child(run_env: &RunEnv, child: &ChkInstance) -> ChkResult {
+    debug!(
+        "RUNNING CHILD {} (negated? {})",
+        &child.fn_def.name, &child.negated
+    );
+    let child_result = child.run_check_maybe_retry(run_env);
+    debug!("Child result: {:#?}", &child_result.result);
+    child_result
+}
+
+// Children after a short circuit are reported as skipped
+fn run_children(
+    run_env: &RunEnv,
+    children: &[ChkInstance],
+    short_circuit: Option<ShortCircuit>,
+) -> Vec<ChkResult> {
+    let mut decided = false;
+    children
+        .iter()
+        .map(|child| {
+            if decided {
+                return child.report_skipped(run_env);
+            }
+            let r = run_child(run_env, child);
+            decided = short_circuit.is_some_and(|sc| sc(&r));
+            r
+        })
+        .collect()
+}
+
+// Each child sends it's events to it's own channel while it runs, and the group passes
+// them on in source order: the events of the first unfinished child as they happen, then
+// the buffered events of the children after it once it's done. Formatters see the same
+// events they would if the children ran one after another.
+// With a short circuit, children that haven't started yet when the group's result is
+// decided are skipped. Children that are already running are allowed to finish.
+fn run_children_parallel(
+    run_env: &RunEnv,
+    children: &[ChkInstance],
+    jobs: usize,
+    short_circuit: Option<ShortCircuit>,
+) -> Vec<ChkResult> {
+    let next_child = AtomicUsize::new(0);
+    let decided = AtomicBool::new(false);
+    let (senders, receivers): (Vec<_>, Vec<_>) = children
+        .iter()
+        .map(|_| {
+            let (tx, rx) = channel();
+            (Mutex::new(Some(tx)), rx)
+        })
+        .unzip();
+    let results: Vec<Mutex<Option<ChkResult>>> =
+        children.iter().map(|_| Mutex::new(None)).collect();
+
+    thread::scope(|s| {
+        for _ in 0..jobs.min(children.len()) {
+            s.spawn(|| loop {
+                let i = next_child.fetch_add(1, Ordering::SeqCst);
+                let Some(child) = children.get(i) else {
+                    break;
+                };
+                // the group is done with a child's events once it's sender is dropped,
+                // which is right away for a child that doesn't run
+                let tx = senders[i].lock().unwrap().take().unwrap();
+                if decided.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let child_env = RunEnv {
+                    emitter: run_env.emitter.as_ref().map(|_| tx.clone()),
+                    global_config: run_env.global_config.clone(),
+                    job_slots: run_env.job_slots.clone(),
+                };
+                let r = run_child(&child_env, child);
+                if short_circuit.is_some_and(|sc| sc(&r)) {
+                    decided.store(true, Ordering::SeqCst);
+                }
+                *results[i].lock().unwrap() = Some(r);
+            });
+        }
+
+        children
+            .iter()
+            .zip(receivers)
+            .zip(&results)
+            .map(|((child, rx), result)| {
+                rx.iter().for_each(|e| run_env.emit(e));
+                result
+                    .lock()
+                    .unwrap()
+                    .take()
+                    .unwrap_or_else(|| child.report_skipped(run_env))
+            })
+            .collect()
+    })
 }
 
 fn agg_exec(
     agg_type: AggType,
     run_env: &RunEnv,
-    _: &ChkActualParams,
+    params: &ChkActualParams,
     this: &ChkInstance,
 ) -> ChkResult {
-    let mut child_results: Vec<ChkResult> = vec![];
-
-    for child in &this.children {
+    let jobs = group_jobs(run_env, params);
+    let short_circuit = short_circuit(&agg_type, params);
+    let child_results: Vec<ChkResult> = if jobs > 1 {
         debug!(
-            "RUNNING CHILD {} (negated? {})",
-            &child.fn_def.name, &child.negated
+            "Running {} children with {} jobs",
+            this.children.len(),
+            jobs
         );
-        let child_result = child.run_check_maybe_retry(run_env);
-        //child_result.result.as_ref().iter().for_each(|x| println!(" --> {}", x));
-        debug!("Child result: {:#?}", &child_result.result);
-        child_results.push(child_result);
-    }
+        run_children_parallel(run_env, &this.children, jobs, short_circuit)
+    } else {
+        run_children(run_env, &this.children, short_circuit)
+    };
 
     if this.children.is_empty() {
         return ChkResult {
             result: Err("pred.all has no children".to_owned()),
             process_out: None,
             children_results: None,
+            skipped: false,
+            wait_stats: None,
         };
     }
 
     debug!(">>>>>> {:#?}", &child_results);
+    // a group where every child was skipped is skipped as well
+    if child_results.iter().all(|r| r.is_check_skipped()) {
+        return ChkResult {
+            children_results: Some(child_results),
+            ..ChkResult::skipped()
+        };
+    }
+
+    let ran: Vec<&ChkResult> = child_results
+        .iter()
+        .filter(|r| !r.is_check_skipped())
+        .collect();
     let agg = match agg_type {
-        AggType::All => agg_all(&child_results),
-        AggType::Any => agg_any(&child_results),
-        AggType::None => agg_none(&child_results),
+        AggType::All => agg_all(&ran),
+        AggType::Any => agg_any(&ran),
+        AggType::None => agg_none(&ran),
+        // the compiler checks that n is between 0 and the number of children, and checks
+        // it again once --tags has pruned them. Children skipped by when: don't count.
+        AggType::AtLeast => agg_passed(&ran) >= threshold(params),
+        AggType::AtMost => agg_passed(&ran) <= threshold(params),
+        AggType::Exactly => agg_passed(&ran) == threshold(params),
     };
     debug!("AGG RESULT {}", agg);
     ChkResult {
         result: Ok(agg),
         process_out: None, // TODO: process_out
         children_results: Some(child_results),
+        skipped: false,
+        wait_stats: None,
     }
 }
 
+fn agg_params() -> ChkFormalParams {
+    FParamsBuilder::new()
+        .add_param(PARALLEL, ChkParamType::PkBool)
+        .not_required()
+        .finish_param()
+        .add_param(SHORT_CIRCUIT, ChkParamType::PkBool)
+        .not_required()
+        .finish_param()
+        .build()
+}
+
 // // TODO: deal with output from children
 pub fn cd_all() -> ChkDef {
     ChkDef {
         name: "all".to_owned(),
-        formal_params: FParamsBuilder::empty(),
+        formal_params: agg_params(),
         is_group: true,
         is_query: false,
         accepts_children: true,
@@ -139,7 +335,7 @@ pub fn cd_all() -> ChkDef {
 pub fn cd_none() -> ChkDef {
     ChkDef {
         name: "none".to_owned(),
-        formal_params: FParamsBuilder::empty(),
+        formal_params: agg_params(),
         is_group: true,
         is_query: false,
         accepts_children: true,
@@ -153,7 +349,7 @@ pub fn cd_none() -> ChkDef {
 pub fn cd_any() -> ChkDef {
     ChkDef {
         name: "any".to_owned(),
-        formal_params: FParamsBuilder::empty(),
+        formal_params: agg_params(),
         is_group: true,
         is_query: false,
         accepts_children: true,
@@ -164,6 +360,48 @@ pub fn cd_any() -> ChkDef {
     }
 }
 
+// the number of children that need to pass for at_least, at_most and exactly
+fn threshold(params: &ChkActualParams) -> usize {
+    params.get(THRESHOLD).unwrap().get_int() as usize
+}
+
+fn threshold_group(name: &str, check_fn: ChkFn) -> ChkDef {
+    ChkDef {
+        name: name.to_owned(),
+        formal_params: FParamsBuilder::new()
+            .add_param(THRESHOLD, ChkParamType::PkInt)
+            .required()
+            .finish_param()
+            .add_param(PARALLEL, ChkParamType::PkBool)
+            .not_required()
+            .finish_param()
+            .build(),
+        is_group: true,
+        is_query: false,
+        accepts_children: true,
+        check_fn,
+        template_params: None,
+    }
+}
+
+pub fn cd_at_least() -> ChkDef {
+    threshold_group("at_least", |run_env, params: &ChkActualParams, this| {
+        agg_exec(AggType::AtLeast, run_env, params, this)
+    })
+}
+
+pub fn cd_at_most() -> ChkDef {
+    threshold_group("at_most", |run_env, params: &ChkActualParams, this| {
+        agg_exec(AggType::AtMost, run_env, params, this)
+    })
+}
+
+pub fn cd_exactly() -> ChkDef {
+    threshold_group("exactly", |run_env, params: &ChkActualParams, this| {
+        agg_exec(AggType::Exactly, run_env, params, this)
+    })
+}
+
 // pub fn cd_not() -> ChkDef {
 //     ChkDef {
 //         name: "not".to_owned(),
@@ -215,6 +453,7 @@ pub fn cd_any() -> ChkDef {
 #[cfg(test)]
 mod tests {
     //use super::*;
+    use crate::predikit::data::events::ChkLifecycleEvent;
 
     // use crate::predikit::data::instance::ChkInstanceBuilder;
     // use crate::predikit::data::instance::RunEnv;
@@ -364,4 +603,362 @@ mod tests {
 
     //     assert!(!check_result.result.unwrap());
     // }
+
+    #[test]
+    fn test_threshold_groups() {
+        use super::*;
+        use crate::predikit::data::instance::ChkInstanceBuilder;
+
+        let t = cd_true();
+        let f = cd_false();
+        let run = |group: &ChkDef, n: i64| {
+            ChkInstanceBuilder::new(group)
+                .param_int(THRESHOLD, n)
+                .add_child(ChkInstanceBuilder::new(&t).build())
+                .add_child(ChkInstanceBuilder::new(&t).build())
+                .add_child(ChkInstanceBuilder::new(&f).build())
+                .build()
+                .run_check_maybe_retry(&RunEnv::default())
+        };
+
+        // 2 of the 3 children pass
+        assert!(run(&cd_at_least(), 2).is_check_pass());
+        assert!(run(&cd_at_least(), 3).is_check_fail());
+        assert!(run(&cd_at_most(), 2).is_check_pass());
+        assert!(run(&cd_at_most(), 1).is_check_fail());
+        assert!(run(&cd_exactly(), 2).is_check_pass());
+        assert!(run(&cd_exactly(), 0).is_check_fail());
+    }
+
+    #[test]
+    fn test_parallel_children() {
+        use super::*;
+        use crate::predikit::data::instance::ChkInstanceBuilder;
+        use crate::predikit::data::params::ChkActualParam;
+        use std::time::{Duration, Instant};
+
+        let sh = cd_shell();
+        let all = cd_all();
+        let sleep = |id: usize, cmd: &str| {
+            ChkInstanceBuilder::new(&sh)
+                .instance_id(id)
+                .param_string("cmd", cmd)
+                .build()
+        };
+        let group = ChkInstanceBuilder::new(&all)
+            .param_bool(PARALLEL, true)
+            .add_child(sleep(1, "sleep 0.4"))
+            .add_child(sleep(2, "sleep 0.2; false"))
+            .add_child(sleep(3, "sleep 0.3"))
+            .add_child(sleep(4, "sleep 0.1"))
+            .build();
+
+        let (tx, rx) = channel();
+        let mut run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        run_env.global_config.insert(
+            JOBS.to_owned(),
+            ChkActualParam::new_int(JOBS.to_owned(), 4, 0..0),
+        );
+        let start = Instant::now();
+        let r = group.run_check_maybe_retry(&run_env);
+        assert!(start.elapsed() < Duration::from_millis(900));
+
+        // results and events are in source order, not the order the children finished in
+        assert!(r.is_check_fail());
+        let results: Vec<bool> = r
+            .children_results
+            .unwrap()
+            .iter()
+            .map(|c| c.is_check_pass())
+            .collect();
+        assert_eq!(vec![true, false, true, true], results);
+
+        drop(run_env);
+        let started: Vec<usize> = rx
+            .iter()
+            .filter_map(|e| match e {
+                ChkLifecycleEvent::CheckStart(id) => Some(id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![1000, 1, 2, 3, 4], started);
+    }
+
+    #[test]
+    fn test_parallel_events_stream() {
+        use super::*;
+        use crate::predikit::data::instance::ChkInstanceBuilder;
+        use std::time::{Duration, Instant};
+
+        let sh = cd_shell();
+        let all = cd_all();
+        let group = ChkInstanceBuilder::new(&all)
+            .param_bool(PARALLEL, true)
+            .add_child(
+                ChkInstanceBuilder::new(&sh)
+                    .instance_id(1)
+                    .param_string("cmd", "true")
+                    .build(),
+            )
+            .add_child(
+                ChkInstanceBuilder::new(&sh)
+                    .instance_id(2)
+                    .param_string("cmd", "sleep 1")
+                    .build(),
+            )
+            .build();
+
+        let (tx, rx) = channel();
+        let run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        let start = Instant::now();
+        thread::scope(|s| {
+            s.spawn(move || group.run_check_maybe_retry(&run_env));
+
+            // the first child's events are passed on while the second is still running
+            let finished = rx
+                .iter()
+                .find(|e| matches!(e, ChkLifecycleEvent::CheckFinish(1, _)));
+            assert!(finished.is_some());
+            assert!(start.elapsed() < Duration::from_millis(800));
+        });
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        use super::*;
+        use crate::predikit::data::instance::ChkInstanceBuilder;
+        use crate::predikit::data::params::ChkActualParam;
+
+        let t = cd_true();
+        let f = cd_false();
+        let sh = cd_shell();
+        let skipped = |r: ChkResult| -> Vec<bool> {
+            r.children_results
+                .unwrap()
+                .iter()
+                .map(|c| c.is_check_skipped())
+                .collect()
+        };
+
+        let all = cd_all();
+        let r = ChkInstanceBuilder::new(&all)
+            .param_bool(SHORT_CIRCUIT, true)
+            .add_child(ChkInstanceBuilder::new(&t).build())
+            .add_child(ChkInstanceBuilder::new(&f).build())
+            .add_child(ChkInstanceBuilder::new(&t).build())
+            .build()
+            .run_check_maybe_retry(&RunEnv::default());
+        assert!(r.is_check_fail());
+        assert_eq!(vec![false, false, true], skipped(r));
+
+        let any = cd_any();
+        let r = ChkInstanceBuilder::new(&any)
+            .param_bool(SHORT_CIRCUIT, true)
+            .add_child(ChkInstanceBuilder::new(&f).build())
+            .add_child(ChkInstanceBuilder::new(&t).build())
+            .add_child(ChkInstanceBuilder::new(&f).build())
+            .build()
+            .run_check_maybe_retry(&RunEnv::default());
+        assert!(r.is_check_pass());
+        assert_eq!(vec![false, false, true], skipped(r));
+
+        // without short_circuit, every child runs
+        let none = cd_none();
+        let r = ChkInstanceBuilder::new(&none)
+            .add_child(ChkInstanceBuilder::new(&t).build())
+            .add_child(ChkInstanceBuilder::new(&f).build())
+            .build()
+            .run_check_maybe_retry(&RunEnv::default());
+        assert!(r.is_check_fail());
+        assert_eq!(vec![false, false], skipped(r));
+
+        // children that hadn't started when a parallel group was decided are skipped
+        let mut run_env = RunEnv::default();
+        run_env.global_config.insert(
+            JOBS.to_owned(),
+            ChkActualParam::new_int(JOBS.to_owned(), 2, 0..0),
+        );
+        let r = ChkInstanceBuilder::new(&all)
+            .param_bool(SHORT_CIRCUIT, true)
+            .add_child(ChkInstanceBuilder::new(&f).build())
+            .add_child(
+                ChkInstanceBuilder::new(&sh)
+                    .param_string("cmd", "sleep 0.3")
+                    .build(),
+            )
+            .add_child(ChkInstanceBuilder::new(&t).build())
+            .add_child(ChkInstanceBuilder::new(&t).build())
+            .build()
+            .run_check_maybe_retry(&run_env);
+        assert!(r.is_check_fail());
+        let skipped = skipped(r);
+        assert!(!skipped[0]);
+        assert_eq!(vec![true, true], skipped[2..]);
+    }
+
+    #[test]
+    fn test_negated_and_retrying_groups() {
+        use super::*;
+        use crate::predikit::data::instance::ChkInstanceBuilder;
+        use crate::predikit::data::retry::RETRIES;
+
+        let t = cd_true();
+        let f = cd_false();
+        let sh = cd_shell();
+        let all = cd_all();
+        let any = cd_any();
+        let chk = |def, id| ChkInstanceBuilder::new(def).instance_id(id).build();
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let marker = tmp_dir.path().join("attempted");
+
+        // every child runs again on each attempt, the shell child only passes on the second
+        let group = ChkInstanceBuilder::new(&all)
+            .instance_id(1)
+            .retrying()
+            .param_int(RETRIES, 3)
+            .add_child(chk(&t, 2))
+            .add_child(
+                ChkInstanceBuilder::new(&sh)
+                    .instance_id(3)
+                    .param_string(
+                        "cmd",
+                        &format!("test -f {0} || {{ touch {0}; false; }}", marker.display()),
+                    )
+                    .build(),
+            )
+            .build();
+
+        let (tx, rx) = channel();
+        let run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        assert!(group.run_check_maybe_retry(&run_env).is_check_pass());
+
+        drop(run_env);
+        let events: Vec<ChkLifecycleEvent> = rx.iter().collect();
+        let starts = |id| {
+            events
+                .iter()
+                .filter(|e| matches!(e, ChkLifecycleEvent::CheckStart(i) if *i == id))
+                .count()
+        };
+        assert_eq!((2, 2, 2), (starts(1), starts(2), starts(3)));
+        let retries: Vec<(usize, u64)> = events
+            .iter()
+            .filter_map(|e| match e {
+                ChkLifecycleEvent::CheckRetry(id, attempt) => Some((*id, *attempt)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![(1, 1)], retries);
+
+        // not flips the result of the whole group, not each child
+        let not_any = |children| {
+            ChkInstanceBuilder::new(&any)
+                .negated(true)
+                .children(children)
+                .build()
+                .run_check_maybe_retry(&RunEnv::default())
+        };
+        assert!(not_any(vec![chk(&f, 1), chk(&f, 2)]).is_check_pass());
+        assert!(not_any(vec![chk(&f, 1), chk(&t, 2)]).is_check_fail());
+    }
+
+    #[test]
+    fn test_skipped_group_children() {
+        use super::*;
+        use crate::predikit::data::instance::ChkInstanceBuilder;
+
+        let t = cd_true();
+        let f = cd_false();
+        let all = cd_all();
+        let any = cd_any();
+        let chk = |def, id| ChkInstanceBuilder::new(def).instance_id(id).build();
+
+        // the nested group after the short circuit is skipped, and so is everything in it
+        let group = ChkInstanceBuilder::new(&all)
+            .instance_id(1)
+            .param_bool(SHORT_CIRCUIT, true)
+            .add_child(chk(&f, 2))
+            .add_child(
+                ChkInstanceBuilder::new(&any)
+                    .instance_id(3)
+                    .add_child(chk(&t, 4))
+                    .add_child(
+                        ChkInstanceBuilder::new(&all)
+                            .instance_id(5)
+                            .add_child(chk(&t, 6))
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+
+        let (tx, rx) = channel();
+        let run_env = RunEnv {
+            emitter: Some(tx),
+            ..RunEnv::default()
+        };
+        assert!(group.run_check_maybe_retry(&run_env).is_check_fail());
+
+        drop(run_env);
+        let skipped: Vec<usize> = rx
+            .iter()
+            .filter_map(|e| match e {
+                ChkLifecycleEvent::CheckSkip(id) => Some(id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![4, 6, 5, 3], skipped);
+    }
+
+    #[test]
+    fn test_nested_parallel_jobs() {
+        use super::*;
+        use crate::predikit::data::instance::ChkInstanceBuilder;
+        use crate::predikit::data::jobs::ChkJobSlots;
+        use crate::predikit::data::params::ChkActualParam;
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        let sh = cd_shell();
+        let all = cd_all();
+        let sleep = || {
+            ChkInstanceBuilder::new(&sh)
+                .param_string("cmd", "sleep 0.3")
+                .build()
+        };
+        let inner = || {
+            ChkInstanceBuilder::new(&all)
+                .add_child(sleep())
+                .add_child(sleep())
+                .build()
+        };
+        let group = ChkInstanceBuilder::new(&all)
+            .add_child(inner())
+            .add_child(inner())
+            .build();
+
+        // each group runs it's children with 2 workers, but only 2 of the 4
+        // checks can run at once in the whole run
+        let mut run_env = RunEnv {
+            job_slots: Some(Arc::new(ChkJobSlots::new(2))),
+            ..RunEnv::default()
+        };
+        run_env.global_config.insert(
+            JOBS.to_owned(),
+            ChkActualParam::new_int(JOBS.to_owned(), 2, 0..0),
+        );
+        let start = Instant::now();
+        let r = group.run_check_maybe_retry(&run_env);
+        assert!(r.is_check_pass());
+        assert!(start.elapsed() >= Duration::from_millis(600));
+    }
 }
@@ -1,7 +1,8 @@
 // Copyright (c) 2025 Dave Parfitt
 
-use crate::predikit::data::instance::{ChkProcessOut, ChkResult};
+use crate::predikit::data::instance::ChkResult;
 use crate::predikit::data::params::ChkActualParams;
+use crate::predikit::data::process::run_shell_check;
 use crate::predikit::data::{ChkDef, ChkParamType, FParamsBuilder};
 use log::debug;
 use std::path::Path;
@@ -84,21 +85,26 @@ pub fn cd_file_exists() -> ChkDef {
             .build(),
         check_fn: |_, params: &ChkActualParams, _| -> ChkResult {
             let p0 = params.get("path").unwrap();
-            let path = p0.get_path();
-            if path.is_err() {
-                return ChkResult {
-                    result: Err(path.unwrap_err()),
-                    process_out: None,
-                    children_results: None,
-                };
-            }
-            let path = path.unwrap();
+            let path = match p0.get_path() {
+                Ok(path) => path,
+                Err(e) => {
+                    return ChkResult {
+                        result: Err(e),
+                        process_out: None,
+                        children_results: None,
+                        skipped: false,
+                        wait_stats: None,
+                    }
+                }
+            };
             let o = Path::new(&path).exists();
             debug!("exists path: {} == {}", path, o);
             ChkResult {
                 result: Ok(o),
                 process_out: None,
                 children_results: None,
+                skipped: false,
+                wait_stats: None,
             }
         },
     }
@@ -125,6 +131,8 @@ pub fn cd_file_is_executable() -> ChkDef {
                 result: Ok(is_exec),
                 process_out: None,
                 children_results: None,
+                skipped: false,
+                wait_stats: None,
             }
         },
     }
@@ -151,50 +159,31 @@ pub fn cd_file_is_on_path() -> ChkDef {
                 result: Ok(w.is_ok()),
                 process_out: None,
                 children_results: None,
+                skipped: false,
+                wait_stats: None,
             }
         },
     }
 }
 
+pub const SHELL: &str = "shell";
+pub const SHELL_CMD: &str = "cmd";
+
 pub fn cd_shell() -> ChkDef {
     ChkDef {
-        name: "shell".to_owned(),
+        name: SHELL.to_owned(),
         is_group: false,
         accepts_children: false,
         template_params: None,
         is_query: false,
         formal_params: FParamsBuilder::new()
-            .add_param("cmd", ChkParamType::PkString)
+            .add_param(SHELL_CMD, ChkParamType::PkString)
             .required()
             .finish_param()
             .build(),
-        check_fn: |_, params: &ChkActualParams, _| -> ChkResult {
-            let cmd = params.get("cmd").unwrap();
-            let cmd_result = std::process::Command::new("sh")
-                .arg("-c")
-                .arg(cmd.get_string())
-                .output();
-
-            match cmd_result {
-                Ok(output) => {
-                    debug!("SHELL RESULT = {}", &output.status.code().unwrap());
-                    debug!("SHELL RESULT 2= {}", &output.status.success());
-                    ChkResult {
-                        result: Ok(output.status.success()),
-                        process_out: Some(ChkProcessOut {
-                            stdout: Some(String::from_utf8_lossy(&output.stdout).to_string()),
-                            stderr: Some(String::from_utf8_lossy(&output.stderr).to_string()),
-                            exit_code: output.status.code(),
-                        }),
-                        children_results: None,
-                    }
-                }
-                Err(e) => ChkResult {
-                    result: Err(e.to_string()),
-                    process_out: None,
-                    children_results: None,
-                },
-            }
+        check_fn: |run_env, params: &ChkActualParams, inst| -> ChkResult {
+            let cmd = params.get(SHELL_CMD).unwrap();
+            run_shell_check(cmd.get_string(), inst.process_timeout(run_env))
         },
     }
 }
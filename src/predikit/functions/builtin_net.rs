@@ -25,6 +25,8 @@ pub fn cd_port_open() -> ChkDef {
                     result: Err("port must be >= 0 && <= 65535)".to_string()),
                     process_out: None,
                     children_results: None,
+                    skipped: false,
+                    wait_stats: None,
                 };
             }
             let v = scan_port(port as u16);
@@ -32,6 +34,8 @@ pub fn cd_port_open() -> ChkDef {
                 result: Ok(v),
                 process_out: None,
                 children_results: None,
+                skipped: false,
+                wait_stats: None,
             }
         },
     }
@@ -57,6 +61,8 @@ pub fn cd_port_addr_open() -> ChkDef {
                 result: Ok(v),
                 process_out: None,
                 children_results: None,
+                skipped: false,
+                wait_stats: None,
             }
         },
     }
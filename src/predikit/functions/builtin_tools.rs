@@ -1,13 +1,81 @@
 // Copyright (c) 2025 Dave Parfitt
 
-use crate::predikit::data::instance::{ChkProcessOut, ChkResult};
+use crate::predikit::data::instance::{ChkInstance, ChkResult};
 use crate::predikit::data::params::ChkActualParams;
+use crate::predikit::data::process::run_shell_check;
 use crate::predikit::data::tools::ToolDef;
 use crate::predikit::data::{formal_params_to_map, ChkDef};
 use handlebars::Handlebars;
 use log::debug;
 use std::collections::HashMap;
 
+// The data that a check's templates are rendered with: the actual params of the check,
+// plus the defaults for any params that the check didn't set
+pub fn template_data(
+    inst: &ChkInstance,
+    params: &ChkActualParams,
+) -> HashMap<String, serde_json::Value> {
+    let mut data = HashMap::new();
+    for (k, v) in params.iter() {
+        data.insert(k.clone(), v.value.to_template_value());
+    }
+    // fill in defaults for any params that the test didn't set
+    for fp in inst.fn_def.formal_params.values() {
+        if let Some(default) = &fp.param_default {
+            if !data.contains_key(&fp.name) {
+                data.insert(fp.name.clone(), default.to_template_value());
+            }
+        }
+    }
+    data
+}
+
+pub fn render_template(
+    template: &str,
+    data: &HashMap<String, serde_json::Value>,
+) -> Result<String, String> {
+    let mut hb = Handlebars::new();
+    // templates render shell commands, not html, so values are used as is
+    hb.register_escape_fn(handlebars::no_escape);
+    hb.render_template(template, data)
+        .map_err(|e| format!("Error rendering template: {}", e))
+}
+
+// Quote a value so the shell sees it as a single word, ex: it's -> 'it'\''s'
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+// Hooks can use the output of a check, so values are quoted for the shell instead of
+// being pasted into the command as is. {{{name}}} renders the raw value.
+pub fn render_hook_template(
+    template: &str,
+    data: &HashMap<String, serde_json::Value>,
+) -> Result<String, String> {
+    let mut hb = Handlebars::new();
+    hb.register_escape_fn(shell_quote);
+    hb.render_template(template, data)
+        .map_err(|e| format!("Error rendering template: {}", e))
+}
+
+// Render a tool's cmd_template with the params of a check instance. This is the
+// command that runs for the check, --dry-run uses it to show the command instead.
+pub fn render_cmd_template(inst: &ChkInstance, params: &ChkActualParams) -> Result<String, String> {
+    // note: missing required params will fail before this function gets called... mostly.
+
+    // template params are stored in the ChkDef
+    let cmd_template = inst
+        .fn_def
+        .template_params
+        .as_ref()
+        .unwrap()
+        .get("cmd_template")
+        .unwrap();
+
+    debug!("cmd_template: {}", cmd_template.get_string());
+    render_template(cmd_template.get_string(), &template_data(inst, params))
+}
+
 // this needs all the refactoring
 pub fn metadef_tool(td: &ToolDef) -> ChkDef {
     ChkDef {
@@ -16,66 +84,25 @@ pub fn metadef_tool(td: &ToolDef) -> ChkDef {
         accepts_children: false,
         formal_params: formal_params_to_map(&td.instance_params),
         template_params: Some(td.template_params.clone()),
-        is_query: false,
-        check_fn: |_, params: &ChkActualParams, inst| -> ChkResult {
+        is_query: td.is_query,
+        check_fn: |run_env, params: &ChkActualParams, inst| -> ChkResult {
             debug!("params: {:#?}", params);
 
-            // note: missing required params will fail before this function gets called... mostly.
-
-            // template params are stored in the ChkDef
-            let cmd_template = inst
-                .fn_def
-                .template_params
-                .as_ref()
-                .unwrap()
-                .get("cmd_template")
-                .unwrap();
-
-            let hb = Handlebars::new();
-            let mut data = HashMap::new();
-            for (k, v) in params.iter() {
-                data.insert(k.clone(), v.value_as_string());
-            }
-            let rendered_cmd = hb.render_template(cmd_template.get_string(), &data);
-            if rendered_cmd.is_err() {
-                return ChkResult {
-                    result: Err(format!(
-                        "Error rendering template: {}",
-                        rendered_cmd.err().unwrap()
-                    )),
-                    process_out: None,
-                    children_results: None,
-                };
-            }
-            let rendered_cmd = rendered_cmd.unwrap();
-            debug!("cmd_template: {}", cmd_template.get_string());
-            debug!("rendered: [{}]", rendered_cmd);
-
-            let cmd_result = std::process::Command::new("sh")
-                .arg("-c")
-                .arg(rendered_cmd)
-                .output();
-
-            match cmd_result {
-                Ok(output) => {
-                    //println!("SHELL RESULT = {}", &output.status.code().unwrap());
-                    //debug!("SHELL RESULT 2= {}", &output.status.success());
-                    ChkResult {
-                        result: Ok(output.status.success()),
-                        process_out: Some(ChkProcessOut {
-                            stdout: Some(String::from_utf8_lossy(&output.stdout).to_string()),
-                            stderr: Some(String::from_utf8_lossy(&output.stderr).to_string()),
-                            exit_code: output.status.code(),
-                        }),
+            let rendered_cmd = match render_cmd_template(inst, params) {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    return ChkResult {
+                        result: Err(e),
+                        process_out: None,
                         children_results: None,
+                        skipped: false,
+                        wait_stats: None,
                     }
                 }
-                Err(e) => ChkResult {
-                    result: Err(e.to_string()),
-                    process_out: None,
-                    children_results: None,
-                },
-            }
+            };
+            debug!("rendered: [{}]", rendered_cmd);
+
+            run_shell_check(&rendered_cmd, inst.process_timeout(run_env))
         },
     }
 }
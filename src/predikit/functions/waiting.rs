@@ -1,218 +1,267 @@
 // Copyright (c) 2025 Dave Parfitt
 
-// use crate::predikit::data::{ChkInstance, ChkResult, RunEnv};
-// use std::fmt;
-//
-// #[derive(Debug, PartialEq, Eq)]
-// pub struct WaitResult {
-//     check_result: ChkResult,
-//     iterations: u64,
-//     elapsed_time: u64,
-// }
-//
-// impl fmt::Display for WaitResult {
-//     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-//         write!(f, "{}", self.check_result)?;
-//         write!(f, "\nIterations: {}", self.iterations)?;
-//         write!(f, "\nElapsed time: {}ms", self.elapsed_time)
-//     }
-// }
-//
-// pub fn wait_until_pass(
-//     check_instance: ChkInstance,
-//     run_env: &RunEnv,
-//     timeout: u64,
-//     interval: u64,
-// ) -> WaitResult {
-//     wait_until_predicate(
-//         check_instance,
-//         run_env,
-//         timeout,
-//         interval,
-//         |result| matches!(result.result, Ok(true)),
-//     )
-// }
-//
-// #[allow(dead_code)]
-// pub fn wait_until_fail(
-//     check_instance: ChkInstance,
-//     run_env: &RunEnv,
-//     timeout: u64,
-//     interval: u64,
-// ) -> WaitResult {
-//     wait_until_predicate(
-//         check_instance,
-//         run_env,
-//         timeout,
-//         interval,
-//         |result| matches!(result.result, Ok(false)),
-//     )
-// }
-// #[allow(dead_code)]
-// pub fn wait_until_err(
-//     check_instance: ChkInstance,
-//     run_env: &RunEnv,
-//     timeout: u64,
-//     interval: u64,
-// ) -> WaitResult {
-//     wait_until_predicate(
-//         check_instance,
-//         run_env,
-//         timeout,
-//         interval,
-//         |result| result.result.is_err(),
-//     )
-// }
-//
-// fn wait_until_predicate<F>(
-//     check_instance: ChkInstance,
-//     run_env: &RunEnv,
-//     timeout: u64,
-//     interval: u64,
-//     predicate: F,
-// ) -> WaitResult
-// where
-//     F: Fn(&ChkResult) -> bool,
-// {
-//     // Validate interval is not zero
-//     if interval == 0 {
-//         return WaitResult {
-//             check_result: ChkResult {
-//                 result: Err("Interval must be greater than 0".to_owned()),
-//                 process_out: None,
-//                 children_results: None,
-//             },
-//             iterations: 0,
-//             elapsed_time: 0,
-//         };
-//     }
-//
-//     // Validate timeout is not zero
-//     if timeout == 0 {
-//         return WaitResult {
-//             check_result: ChkResult {
-//                 result: Err("Timeout must be greater than 0".to_owned()),
-//                 process_out: None,
-//                 children_results: None,
-//             },
-//             iterations: 0,
-//             elapsed_time: 0,
-//         };
-//     }
-//
-//     if timeout < interval {
-//         return WaitResult {
-//             check_result: ChkResult {
-//                 result: Err("Timeout must be greater than interval".to_owned()),
-//                 process_out: None,
-//                 children_results: None,
-//             },
-//             iterations: 0,
-//             elapsed_time: 0,
-//         };
-//     }
-//
-//     let start = std::time::Instant::now();
-//     let mut elapsed = 0;
-//     let mut iterations = 0;
-//
-//     while elapsed < timeout {
-//         iterations += 1;
-//         let check_result = check_instance.run_check(run_env);
-//         if predicate(&check_result) {
-//             return WaitResult {
-//                 check_result,
-//                 iterations,
-//                 elapsed_time: elapsed,
-//             };
-//         }
-//         std::thread::sleep(std::time::Duration::from_millis(interval));
-//         elapsed = start.elapsed().as_millis() as u64;
-//     }
-//
-//     WaitResult {
-//         check_result: ChkResult {
-//             result: Err(format!("Timeout after {}ms", elapsed)),
-//             process_out: None,
-//             children_results: None,
-//         },
-//         iterations,
-//         elapsed_time: elapsed,
-//     }
-// }
-//
-//
-// // TODO: this is more like an integration test, it should probably move
-// #[cfg(test)]
-// mod tests {
-//     use crate::predikit::functions::builtin::define_builtins;
-//     use crate::predikit::data::{ChkDefRegistry, ChkInstance, RunEnv};
-//     use std::collections::HashMap;
-//
-//     use crate::predikit::functions::waiting::wait_until_pass;
-//     use async_std::task;
-//     use std::time::Duration;
-//
-//     // TODO: This is copy/pasta from builtin.rs
-//     fn test_param(param_name: &str, param_value: &str) -> HashMap<String, String> {
-//         let mut params: HashMap<String, String> = HashMap::new();
-//         params.insert(param_name.to_owned(), param_value.to_owned());
-//         return params;
-//     }
-//
-//     // TODO: This is copy/pasta from builtin.rs
-//     fn param_filename(value: &str) -> HashMap<String, String> {
-//         test_param("filename", value)
-//     }
-//
-//     // Take a function parameter that returns no value, and execute
-//     // the function after a delay in millis
-//     fn run_after_delay<F>(delay_ms: u64, f: F)
-//     where
-//         F: FnOnce() + Send + 'static,
-//     {
-//         task::spawn(async move {
-//             task::sleep(Duration::from_millis(delay_ms)).await;
-//             f();
-//         });
-//     }
-//
-//
-//     #[test]
-//     fn test_wait_until1() {
-//         let run_env = RunEnv::default();
-//
-//         let tmp_dir = tempfile::tempdir().unwrap();
-//         let tmp_path = tmp_dir.path();
-//
-//         let testfile = format!("{}/foobar123", tmp_path.to_string_lossy().to_string());
-//
-//         // TODO: make this easier to setup and use for tests
-//         let mut reg = ChkDefRegistry::default();
-//         let bis = define_builtins();
-//         bis.into_iter()
-//             .for_each(|c| reg.register_checkdef(c.name.clone(), c));
-//
-//         let ci = ChkInstance {
-//             name: "test_wait_until1".to_owned(),
-//             check_def: &reg.get_checkdef("exists?").unwrap(),
-//             actual_params: param_filename(&testfile),
-//             children: None,
-//             negated: false,
-//             parsed_check: None,
-//         };
-//
-//         run_after_delay(1000, move || {
-//             let c = format!("touch {}", testfile);
-//             let out = std::process::Command::new("sh")
-//                 .arg("-c")
-//                 .arg(c).output().expect("Couldn't run background task");
-//             let _ = String::from_utf8_lossy(&out.stdout);
-//             // not sure if I care about the output
-//         });
-//
-//         let wait_result = wait_until_pass(ci, &run_env, 3000, 100);
-//         //println!("Wait result: {:?}", &wait_result);
-//         assert!(wait_result.check_result.result.unwrap());
-//     }
-// }
\ No newline at end of file
+use crate::predikit::data::events::ChkLifecycleEvent;
+use crate::predikit::data::instance::{ChkInstance, ChkResult, RunEnv};
+use crate::predikit::data::params::ChkActualParams;
+use crate::predikit::data::{ChkDef, ChkFn, ChkParamType, FParamsBuilder};
+use log::debug;
+use std::time::{Duration, Instant};
+
+pub const WAIT_FOR: &str = "wait_for";
+pub const WAIT_INTERVAL: &str = "interval";
+
+/// How long a wait group waited for it's children, reported in the ChkResult of the group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChkWaitStats {
+    pub iterations: u64,
+    pub elapsed: Duration,
+}
+
+pub fn define_waits() -> Vec<ChkDef> {
+    vec![
+        cd_wait_until_pass(),
+        cd_wait_until_fail(),
+        cd_wait_until_err(),
+    ]
+}
+
+// ex: wait until_pass wait_for: d(2m) interval: d(1s) { test port_open? { port: 5432 } }
+fn wait_group(name: &str, check_fn: ChkFn) -> ChkDef {
+    ChkDef {
+        name: name.to_owned(),
+        formal_params: FParamsBuilder::new()
+            .add_param(WAIT_FOR, ChkParamType::PkDuration)
+            .required()
+            .finish_param()
+            .add_param(WAIT_INTERVAL, ChkParamType::PkDuration)
+            .required()
+            .finish_param()
+            .build(),
+        is_group: true,
+        is_query: false,
+        accepts_children: true,
+        check_fn,
+        template_params: None,
+    }
+}
+
+pub fn cd_wait_until_pass() -> ChkDef {
+    wait_group("wait_until_pass", |run_env, params, this| {
+        wait_until_predicate(run_env, params, this, |r| r.is_check_pass())
+    })
+}
+
+pub fn cd_wait_until_fail() -> ChkDef {
+    wait_group("wait_until_fail", |run_env, params, this| {
+        wait_until_predicate(run_env, params, this, |r| r.is_check_fail())
+    })
+}
+
+pub fn cd_wait_until_err() -> ChkDef {
+    wait_group("wait_until_error", |run_env, params, this| {
+        wait_until_predicate(run_env, params, this, |r| r.is_check_error())
+    })
+}
+
+fn wait_error(msg: String, wait_stats: Option<ChkWaitStats>) -> ChkResult {
+    ChkResult {
+        result: Err(msg),
+        process_out: None,
+        children_results: None,
+        skipped: false,
+        wait_stats,
+    }
+}
+
+// Run all of the children once. An error in any child is an error, otherwise
+// the iteration passes if every child passes. Skipped children are ignored.
+fn run_iteration(run_env: &RunEnv, this: &ChkInstance) -> ChkResult {
+    let children_results: Vec<ChkResult> = this
+        .children
+        .iter()
+        .map(|child| child.run_check_maybe_retry(run_env))
+        .collect();
+
+    let result = match children_results.iter().find(|r| r.is_check_error()) {
+        Some(err) => err.result.clone(),
+        None => Ok(children_results
+            .iter()
+            .all(|r| r.is_check_pass() || r.is_check_skipped())),
+    };
+    ChkResult {
+        result,
+        process_out: None,
+        children_results: Some(children_results),
+        skipped: false,
+        wait_stats: None,
+    }
+}
+
+// The children are run silently on each iteration, the wait group emits a
+// CheckWaitTick event after each iteration instead so formatters can show a countdown.
+fn wait_until_predicate<F>(
+    run_env: &RunEnv,
+    params: &ChkActualParams,
+    this: &ChkInstance,
+    predicate: F,
+) -> ChkResult
+where
+    F: Fn(&ChkResult) -> bool,
+{
+    // the compiler checks that interval is greater than 0 and no more than wait_for
+    let timeout = params.get(WAIT_FOR).unwrap().get_duration().duration;
+    let interval = params.get(WAIT_INTERVAL).unwrap().get_duration().duration;
+
+    if interval.is_zero() {
+        return wait_error("Interval must be greater than 0".to_owned(), None);
+    }
+
+    if timeout.is_zero() {
+        return wait_error("wait_for must be greater than 0".to_owned(), None);
+    }
+
+    if timeout < interval {
+        return wait_error(
+            "wait_for must be at least as long as interval".to_owned(),
+            None,
+        );
+    }
+
+    if this.children.is_empty() {
+        return wait_error(format!("{} has no children", this.fn_def.name), None);
+    }
+
+    let silent_env = RunEnv {
+        emitter: None,
+        global_config: run_env.global_config.clone(),
+        job_slots: run_env.job_slots.clone(),
+    };
+
+    let start = Instant::now();
+    let mut iterations = 0;
+    loop {
+        iterations += 1;
+        let iteration_result = run_iteration(&silent_env, this);
+        let elapsed = start.elapsed();
+        run_env.emit(ChkLifecycleEvent::CheckWaitTick(
+            this.instance_id,
+            iterations,
+            elapsed,
+            timeout,
+        ));
+        debug!(
+            "{} iteration {}: {:?}",
+            this.fn_def.name, iterations, iteration_result.result
+        );
+
+        let wait_stats = Some(ChkWaitStats {
+            iterations,
+            elapsed,
+        });
+        if predicate(&iteration_result) {
+            // the wait group passes when the children reach the state it's waiting for
+            return ChkResult {
+                result: Ok(true),
+                wait_stats,
+                ..iteration_result
+            };
+        }
+
+        if elapsed + interval > timeout {
+            return wait_error(
+                format!(
+                    "Timeout after {}ms ({} iterations)",
+                    elapsed.as_millis(),
+                    iterations
+                ),
+                wait_stats,
+            );
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predikit::data::instance::ChkInstanceBuilder;
+    use crate::predikit::data::params::ChkActualParam;
+    use crate::predikit::data::ParsedDuration;
+    use crate::predikit::functions::builtin::{cd_false, cd_true};
+    use crate::predikit::functions::builtin_fs::cd_file_exists;
+
+    fn duration_param(name: &str, ms: u64) -> ChkActualParam {
+        let d = ParsedDuration::new(Duration::from_millis(ms), format!("{}ms", ms));
+        ChkActualParam::new_duration(name.to_owned(), d, 0..0)
+    }
+
+    fn wait<'a>(group: &'a ChkDef, child: ChkInstance<'a>, timeout_ms: u64) -> ChkResult {
+        ChkInstanceBuilder::new(group)
+            .param(WAIT_FOR, duration_param(WAIT_FOR, timeout_ms))
+            .param(WAIT_INTERVAL, duration_param(WAIT_INTERVAL, 20))
+            .add_child(child)
+            .build()
+            .run_check_maybe_retry(&RunEnv::default())
+    }
+
+    #[test]
+    fn test_wait_until_pass() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let testfile = tmp_dir.path().join("foobar123");
+        let fe = cd_file_exists();
+        let child = ChkInstanceBuilder::new(&fe)
+            .param_string("path", &testfile.to_string_lossy())
+            .build();
+
+        let touch = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            std::fs::write(testfile, "").unwrap();
+        });
+
+        let r = wait(&cd_wait_until_pass(), child, 5000);
+        touch.join().unwrap();
+        assert!(r.is_check_pass());
+        assert!(r.wait_stats.unwrap().iterations > 1);
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let f = cd_false();
+        let r = wait(
+            &cd_wait_until_pass(),
+            ChkInstanceBuilder::new(&f).build(),
+            100,
+        );
+        assert!(r.is_check_error());
+        let stats = r.wait_stats.unwrap();
+        assert!(stats.iterations >= 2);
+        assert!(stats.elapsed < Duration::from_millis(100));
+
+        let t = cd_true();
+        let r = wait(
+            &cd_wait_until_fail(),
+            ChkInstanceBuilder::new(&f).build(),
+            100,
+        );
+        assert!(r.is_check_pass());
+        assert_eq!(1, r.wait_stats.unwrap().iterations);
+
+        let r = wait(
+            &cd_wait_until_err(),
+            ChkInstanceBuilder::new(&t).build(),
+            100,
+        );
+        assert!(r.is_check_error());
+
+        // an interval longer than wait_for is an error
+        let r = wait(
+            &cd_wait_until_pass(),
+            ChkInstanceBuilder::new(&t).build(),
+            10,
+        );
+        assert_eq!(
+            Err("wait_for must be at least as long as interval".to_owned()),
+            r.result
+        );
+    }
+}